@@ -0,0 +1,74 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use types::block::{BlockBody, BlockBodyBuilder};
+use types::transaction::SignedUserTransaction;
+
+/// Build a body transaction by transaction, hash it after every append or only once at the end.
+fn bench_build_body(c: &mut Criterion) {
+    let txns = (0..10_000)
+        .map(|_| SignedUserTransaction::mock())
+        .collect::<Vec<_>>();
+    let mut group = c.benchmark_group("build_body");
+    group.sample_size(10);
+    group.bench_with_input(
+        BenchmarkId::new("hash_every_push", txns.len()),
+        &txns,
+        |b, txns| {
+            b.iter(|| {
+                let mut body = BlockBody::new_empty();
+                for txn in txns {
+                    body.push_transaction(txn.clone());
+                    body.hash();
+                }
+                body.hash()
+            })
+        },
+    );
+    group.bench_with_input(
+        BenchmarkId::new("hash_once", txns.len()),
+        &txns,
+        |b, txns| {
+            b.iter(|| {
+                let mut body = BlockBody::new_empty();
+                for txn in txns {
+                    body.push_transaction(txn.clone());
+                }
+                body.hash()
+            })
+        },
+    );
+    group.finish();
+}
+
+/// Hash a built body ten times, recomputing it every time or memoized by the builder.
+fn bench_rehash_body(c: &mut Criterion) {
+    let mut builder = BlockBodyBuilder::new(None);
+    builder.extend_transactions((0..10_000).map(|_| SignedUserTransaction::mock()));
+    let body = builder.clone().build();
+    let mut group = c.benchmark_group("rehash_body");
+    group.sample_size(10);
+    group.bench_function("hash", |b| {
+        b.iter(|| {
+            for _ in 0..10 {
+                body.hash();
+            }
+        })
+    });
+    group.bench_function("hash_cached", |b| {
+        b.iter_batched(
+            || builder.clone(),
+            |mut builder| {
+                for _ in 0..10 {
+                    builder.hash_cached();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_build_body, bench_rehash_body);
+criterion_main!(benches);
@@ -1,7 +1,7 @@
 // Copyright (c) The Starcoin Core Contributors
 // SPDX-License-Identifier: Apache-2.0
 
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use crypto::hash::*;
 use forkable_jellyfish_merkle::blob::Blob;
 use forkable_jellyfish_merkle::HashValueKey;
@@ -53,6 +53,41 @@ fn bench_get_with_proof(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_concurrent_get(c: &mut Criterion) {
+    ::logger::init_for_test();
+    let mem_store = Arc::new(MockStateNodeStore::new()) as Arc<dyn StateNodeStore>;
+    let tree = Arc::new(StateTree::new(mem_store, None));
+    let (kvs, _root) = prepare_tree(&tree, &[3, 4, 5, 6], 100_000);
+    let ks = Arc::new(kvs.keys().copied().collect::<Vec<_>>());
+    let gets_per_thread = 1_000usize;
+
+    let mut group = c.benchmark_group("concurrent_get");
+    group.sample_size(20);
+    for threads in vec![1usize, 2, 4, 8].into_iter() {
+        group.throughput(Throughput::Elements((threads * gets_per_thread) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, n| {
+            b.iter(|| {
+                let handles = (0..*n)
+                    .map(|t| {
+                        let tree = tree.clone();
+                        let ks = ks.clone();
+                        std::thread::spawn(move || {
+                            for i in 0..gets_per_thread {
+                                let k = &ks[(t * gets_per_thread + i) % ks.len()];
+                                assert!(tree.get(k).unwrap().is_some());
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
 fn bench_put_and_commit(c: &mut Criterion) {
     ::logger::init_for_test();
     let mut group = c.benchmark_group("put_and_commit");
@@ -104,9 +139,14 @@ criterion_group!(
     name=benches;
     config = Criterion::default()
     .with_profiler(PProfProfiler::new(100, Output::Flamegraph(None)));
-    targets=bench_get_with_proof, bench_put_and_commit);
+    targets=bench_get_with_proof, bench_put_and_commit, bench_concurrent_get);
 #[cfg(not(target_os = "linux"))]
-criterion_group!(benches, bench_get_with_proof, bench_put_and_commit);
+criterion_group!(
+    benches,
+    bench_get_with_proof,
+    bench_put_and_commit,
+    bench_concurrent_get
+);
 criterion_main!(benches);
 
 fn gen_kv_from_seed(seed: &[u8], num_keys: usize) -> HashMap<HashValueKey, Blob> {
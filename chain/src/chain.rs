@@ -344,7 +344,7 @@ impl BlockChain {
             .storage
             .get_block_info(block.id())?
             .ok_or_else(|| format_err!("Can not find block info by hash {:?}", block.id()))?;
-        self.connect(ExecutedBlock { block, block_info })
+        self.connect(ExecutedBlock::new(block, block_info))
     }
 
     //TODO consider move this logic to BlockExecutor
@@ -469,6 +469,7 @@ impl BlockChain {
             "events' length should be equal to txn infos' length"
         );
         let txn_info_ids: Vec<_> = txn_infos.iter().map(|info| info.id()).collect();
+        let gas_used_per_txn: Vec<u64> = txn_infos.iter().map(|info| info.gas_used()).collect();
         for (info_id, events) in txn_info_ids.iter().zip(txn_events.into_iter()) {
             storage.save_contract_events(*info_id, events)?;
         }
@@ -506,7 +507,7 @@ impl BlockChain {
         storage.save_block_info(block_info.clone())?;
 
         watch(CHAIN_WATCH_NAME, "n26");
-        Ok(ExecutedBlock { block, block_info })
+        Ok(ExecutedBlock::new(block, block_info).with_gas_used_per_txn(gas_used_per_txn))
     }
 
     pub fn get_txn_accumulator(&self) -> &MerkleAccumulator {
@@ -3,7 +3,7 @@
 
 use crate::blob::Blob;
 use crate::node_type::{SparseMerkleInternalNode, SparseMerkleLeafNode};
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, ensure, format_err, Result};
 use serde::{Deserialize, Serialize};
 use starcoin_crypto::hash::*;
 
@@ -229,4 +229,102 @@ impl SparseMerkleRangeProof {
     pub fn right_siblings(&self) -> &[HashValue] {
         &self.right_siblings
     }
+
+    /// Verifies that `leaves` are exactly all the leaves from the leftmost one to the last one in
+    /// the tree with `expected_root_hash`. `leaves` are `(key, value_hash)` pairs with strictly
+    /// increasing keys, and the last one is the leaf this proof is generated for.
+    /// Because the proof only carries the siblings on the right, all leaves left of the last one
+    /// must be provided, so paginated results are verified cumulatively.
+    pub fn verify(
+        &self,
+        expected_root_hash: HashValue,
+        leaves: &[(HashValue, HashValue)],
+    ) -> Result<()> {
+        ensure!(!leaves.is_empty(), "Range proof needs at least one leaf.");
+        ensure!(
+            leaves.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "Leaves in range proof are not strictly increasing."
+        );
+        let (rightmost_key, rightmost_value_hash) = leaves[leaves.len() - 1];
+        let rightmost_bits: Vec<bool> = rightmost_key.iter_bits().collect();
+
+        // Walk down along the path of the rightmost leaf to find the depth of it. The leaf stops at
+        // the first position where it's the only known leaf and no right sibling remain.
+        let mut remaining_right_siblings = self.right_siblings.len();
+        let mut known_leaves = leaves;
+        let mut depth = 0;
+        while known_leaves.len() > 1 || remaining_right_siblings > 0 {
+            ensure!(
+                depth < HashValue::LENGTH_IN_BITS,
+                "Range proof path is longer than {} bits.",
+                HashValue::LENGTH_IN_BITS
+            );
+            if !rightmost_bits[depth] {
+                ensure!(
+                    remaining_right_siblings > 0,
+                    "Range proof has too few right siblings."
+                );
+                remaining_right_siblings -= 1;
+            }
+            let split = known_leaves
+                .iter()
+                .position(|(key, _)| key_bit(key, depth) == rightmost_bits[depth])
+                .unwrap_or(known_leaves.len());
+            known_leaves = &known_leaves[split..];
+            depth += 1;
+        }
+
+        let mut right_siblings = self.right_siblings.iter();
+        let mut current_hash =
+            SparseMerkleLeafNode::new(rightmost_key, rightmost_value_hash).crypto_hash();
+        for d in (0..depth).rev() {
+            current_hash = if rightmost_bits[d] {
+                let left_leaves: Vec<(HashValue, HashValue)> = leaves
+                    .iter()
+                    .filter(|(key, _)| {
+                        key.common_prefix_bits_len(rightmost_key) == d && !key_bit(key, d)
+                    })
+                    .copied()
+                    .collect();
+                let left_hash = subtree_hash(&left_leaves, d + 1);
+                SparseMerkleInternalNode::new(left_hash, current_hash).crypto_hash()
+            } else {
+                let right_hash = right_siblings
+                    .next()
+                    .ok_or_else(|| format_err!("Range proof has too few right siblings."))?;
+                SparseMerkleInternalNode::new(current_hash, *right_hash).crypto_hash()
+            };
+        }
+        ensure!(
+            current_hash == expected_root_hash,
+            "Root hashes do not match. Actual root hash: {:x}. Expected root hash: {:x}.",
+            current_hash,
+            expected_root_hash,
+        );
+        Ok(())
+    }
+}
+
+fn key_bit(key: &HashValue, index: usize) -> bool {
+    key.iter_bits().nth(index).unwrap_or(false)
+}
+
+/// Root hash of the subtree at `depth` which contains exactly `leaves`.
+fn subtree_hash(leaves: &[(HashValue, HashValue)], depth: usize) -> HashValue {
+    match leaves {
+        [] => *SPARSE_MERKLE_PLACEHOLDER_HASH,
+        [(key, value_hash)] => SparseMerkleLeafNode::new(*key, *value_hash).crypto_hash(),
+        _ => {
+            let split = leaves
+                .iter()
+                .position(|(key, _)| key_bit(key, depth))
+                .unwrap_or(leaves.len());
+            let (left, right) = leaves.split_at(split);
+            SparseMerkleInternalNode::new(
+                subtree_hash(left, depth + 1),
+                subtree_hash(right, depth + 1),
+            )
+            .crypto_hash()
+        }
+    }
 }
@@ -5,9 +5,9 @@
 
 use crate::consensus::Consensus;
 use crate::difficulty::{get_next_target_helper, BlockDiffInfo};
-use crate::{difficult_to_target, target_to_difficulty, CRYPTONIGHT};
+use crate::{difficult_to_target, set_header_nonce, target_to_difficulty, CRYPTONIGHT};
 use starcoin_crypto::hash::PlainCryptoHash;
-use starcoin_types::block::{BlockHeader, BlockHeaderBuilder, RawBlockHeader};
+use starcoin_types::block::{BlockHeader, BlockHeaderBuilder, BlockHeaderExtra, RawBlockHeader};
 use starcoin_types::U256;
 use starcoin_vm_types::time::{
     duration_since_epoch, MockTimeService, TimeService, TimeServiceType,
@@ -29,6 +29,20 @@ fn raw_hash_test() {
     assert_eq!(raw_id_1, raw_id_2);
 }
 
+#[stest::test]
+fn pow_blob_with_nonce_test() {
+    let header = BlockHeader::random()
+        .as_builder()
+        .with_extra(BlockHeaderExtra::new([1u8, 2, 3, 4]))
+        .build();
+    for nonce in [0u32, 42, u32::MAX] {
+        assert_eq!(
+            header.pow_blob_with_nonce(nonce),
+            set_header_nonce(&header.as_pow_header_blob(), nonce, header.extra())
+        );
+    }
+}
+
 #[stest::test]
 fn verify_header_test() {
     let header = BlockHeaderBuilder::random()
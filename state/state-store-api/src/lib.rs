@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use forkable_jellyfish_merkle::node_type::Node;
 use forkable_jellyfish_merkle::RawKey;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -54,4 +54,15 @@ pub trait StateNodeStore: std::marker::Send + std::marker::Sync {
     fn get(&self, hash: &HashValue) -> Result<Option<StateNode>>;
     fn put(&self, key: HashValue, node: StateNode) -> Result<()>;
     fn write_nodes(&self, nodes: BTreeMap<HashValue, StateNode>) -> Result<()>;
+    fn delete_nodes(&self, _keys: Vec<HashValue>) -> Result<()> {
+        bail!("delete_nodes not supported by this state node store.")
+    }
+    /// The root hash recorded by `save_latest_root` most recently, if any.
+    /// Stores which don't record it return `None`.
+    fn get_latest_root(&self) -> Result<Option<HashValue>> {
+        Ok(None)
+    }
+    fn save_latest_root(&self, _root_hash: HashValue) -> Result<()> {
+        bail!("This state node store does not record the latest root.")
+    }
 }
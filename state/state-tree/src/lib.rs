@@ -5,11 +5,24 @@ extern crate core;
 
 pub mod mock;
 mod state_tree;
+mod typed_state_tree;
 
 #[cfg(test)]
 mod state_tree_test;
 
 pub use starcoin_state_store_api::{StateNode, StateNodeStore};
+pub use state_tree::verify_proofs;
+pub use state_tree::verify_proofs_multi;
 pub use state_tree::AccountStateSetIterator;
+pub use state_tree::CacheStats;
+pub use state_tree::MissingNodePolicy;
+pub use state_tree::ProofKind;
+pub use state_tree::ProofServer;
+pub use state_tree::StateDiff;
 pub use state_tree::StateTree;
+pub use state_tree::StateTreeError;
+pub use state_tree::StateTreeSnapshot;
 pub use state_tree::StorageTreeReader;
+pub use state_tree::UndoLog;
+pub use state_tree::{DefaultNodeCodec, NodeCodec};
+pub use typed_state_tree::TypedStateTree;
@@ -10,6 +10,7 @@ use std::collections::{BTreeMap, HashMap};
 #[derive(Default)]
 pub struct MockStateNodeStore {
     nodes: RwLock<HashMap<HashValue, StateNode>>,
+    latest_root: RwLock<Option<HashValue>>,
 }
 
 impl MockStateNodeStore {
@@ -41,4 +42,21 @@ impl StateNodeStore for MockStateNodeStore {
         store_nodes.extend(nodes.into_iter());
         Ok(())
     }
+
+    fn delete_nodes(&self, keys: Vec<HashValue>) -> Result<()> {
+        let mut store_nodes = self.nodes.write();
+        for key in keys {
+            store_nodes.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn get_latest_root(&self) -> Result<Option<HashValue>> {
+        Ok(*self.latest_root.read())
+    }
+
+    fn save_latest_root(&self, root_hash: HashValue) -> Result<()> {
+        *self.latest_root.write() = Some(root_hash);
+        Ok(())
+    }
 }
@@ -1,12 +1,17 @@
-use anyhow::Result;
+use crate::mock::MockStateNodeStore;
+use anyhow::{ensure, format_err, Result};
 use forkable_jellyfish_merkle::blob::Blob;
 use forkable_jellyfish_merkle::iterator::{JellyfishMerkleIntoIterator, JellyfishMerkleIterator};
-use forkable_jellyfish_merkle::node_type::{Node, NodeKey};
-use forkable_jellyfish_merkle::proof::SparseMerkleProof;
+use forkable_jellyfish_merkle::nibble::Nibble;
+use forkable_jellyfish_merkle::node_type::{InternalNode, LeafNode, Node, NodeKey};
+use forkable_jellyfish_merkle::proof::{SparseMerkleProof, SparseMerkleRangeProof};
 use forkable_jellyfish_merkle::{
-    JellyfishMerkleTree, RawKey, StaleNodeIndex, TreeReader, TreeUpdateBatch,
+    HashValueKey, JellyfishMerkleTree, NodeBatch, RawKey, StaleNodeIndex, TreeReader,
+    TreeUpdateBatch,
 };
+use lru::LruCache;
 use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use starcoin_crypto::hash::*;
 use starcoin_state_store_api::*;
 use starcoin_types::access_path::DataType;
@@ -15,10 +20,24 @@ use starcoin_types::account_state::AccountState;
 use starcoin_types::language_storage::StructTag;
 use starcoin_types::state_set::{AccountStateSet, StateSet};
 use starcoin_vm_types::access_path::ModuleName;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Eq, PartialEq, Error)]
+pub enum StateTreeError {
+    /// A node referenced by the tree is not in the cache nor the storage,
+    /// the storage is incomplete, which is different from an absent key.
+    #[error("Missing node at {node_hash:?}.")]
+    MissingNode { node_hash: HashValue },
+}
+
+fn pruning_not_enabled() -> anyhow::Error {
+    format_err!("Pruning is not enabled on this state tree, see `StateTree::enable_pruning`.")
+}
 
 #[derive(Clone)]
 pub struct StateCache<K: RawKey> {
@@ -75,12 +94,216 @@ where
     }
 }
 
+/// Whether a `SparseMerkleProof` proves a key exists or doesn't exist in the tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProofKind {
+    Inclusion,
+    Exclusion,
+}
+
+/// How `StateTree::get` handles a node missing in storage.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MissingNodePolicy {
+    /// Return an error, the default.
+    Error,
+    /// Treat the keys under a missing subtree as absent, return `None`.
+    TreatAsAbsent,
+}
+
+impl Default for MissingNodePolicy {
+    fn default() -> Self {
+        MissingNodePolicy::Error
+    }
+}
+
+/// In-memory state of a `StateTree` at some point, made by `StateTree::snapshot`,
+/// `StateTree::rollback_to` use it to drop the modification made after that point.
+#[derive(Clone)]
+pub struct StateTreeSnapshot<K: RawKey> {
+    storage_root_hash: HashValue,
+    cache: StateCache<K>,
+    updates: BTreeMap<K, Option<Blob>>,
+    num_leaves: Option<u64>,
+}
+
+impl<K> StateTreeSnapshot<K>
+where
+    K: RawKey,
+{
+    /// The committed root hash when the snapshot is made.
+    pub fn root_hash(&self) -> HashValue {
+        self.cache.root_hash
+    }
+}
+
+/// Reverse diff of a commit, recording the prior values of the changed keys,
+/// `StateTree::apply_undo` use it to roll the tree back to `prev_root_hash`.
+#[derive(Clone, Debug)]
+pub struct UndoLog<K: RawKey> {
+    prev_root_hash: HashValue,
+    root_hash: HashValue,
+    prev_values: Vec<(K, Option<Blob>)>,
+}
+
+impl<K> UndoLog<K>
+where
+    K: RawKey,
+{
+    /// The root hash before the commit.
+    pub fn prev_root_hash(&self) -> HashValue {
+        self.prev_root_hash
+    }
+
+    /// The root hash after the commit.
+    pub fn root_hash(&self) -> HashValue {
+        self.root_hash
+    }
+
+    /// The changed keys with their values before the commit, `None` means the key didn't exist.
+    pub fn prev_values(&self) -> &[(K, Option<Blob>)] {
+        self.prev_values.as_slice()
+    }
+}
+
+/// The changes from `from_root` to `to_root`, made by `StateTree::state_diff`,
+/// `StateTree::apply_diff` use it to move a tree at `from_root` to `to_root`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StateDiff<K: RawKey> {
+    from_root: HashValue,
+    to_root: HashValue,
+    changes: Vec<(K, Option<Blob>)>,
+}
+
+impl<K> StateDiff<K>
+where
+    K: RawKey,
+{
+    pub fn from_root(&self) -> HashValue {
+        self.from_root
+    }
+
+    pub fn to_root(&self) -> HashValue {
+        self.to_root
+    }
+
+    /// The changed keys with their values at `to_root`, `None` means the key is removed.
+    pub fn changes(&self) -> &[(K, Option<Blob>)] {
+        self.changes.as_slice()
+    }
+}
+
+/// Encode and decode tree nodes for the underline `StateNodeStore`.
+/// Node hashes are always computed from the canonical node, a codec only changes the stored bytes.
+pub trait NodeCodec<K: RawKey>: Send + Sync {
+    fn encode(&self, node: &Node<K>) -> Result<StateNode>;
+    fn decode(&self, node: StateNode) -> Result<Node<K>>;
+}
+
+/// The default node codec, use `Node::encode` and `Node::decode`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultNodeCodec;
+
+impl<K> NodeCodec<K> for DefaultNodeCodec
+where
+    K: RawKey,
+{
+    fn encode(&self, node: &Node<K>) -> Result<StateNode> {
+        Ok(StateNode(node.encode()?))
+    }
+
+    fn decode(&self, node: StateNode) -> Result<Node<K>> {
+        node.try_into()
+    }
+}
+
+/// A handle serving proofs against the root of a `StateTree` at the time it's created,
+/// nodes read are cached in the handle and shared across `proof` calls.
+/// It becomes stale once a new root is committed to the tree.
+pub struct ProofServer<'a, K: RawKey> {
+    tree: &'a StateTree<K>,
+    root_hash: HashValue,
+    nodes: Mutex<HashMap<NodeKey, Node<K>>>,
+}
+
+impl<'a, K> ProofServer<'a, K>
+where
+    K: RawKey,
+{
+    /// The root hash proofs are served against.
+    pub fn root_hash(&self) -> HashValue {
+        self.root_hash
+    }
+
+    /// Return true if the tree has committed a new root since the server was created.
+    pub fn is_stale(&self) -> bool {
+        self.tree.root_hash() != self.root_hash
+    }
+
+    /// Same as `StateTree::get_with_proof`, error if the server is stale.
+    pub fn proof(&self, key: &K) -> Result<(Option<Vec<u8>>, SparseMerkleProof)> {
+        let cache_guard = self.tree.cache.read();
+        ensure!(
+            cache_guard.root_hash == self.root_hash,
+            "Proof server of root {} is stale, current root is {}.",
+            self.root_hash,
+            cache_guard.root_hash
+        );
+        let inner = CachedTreeReader {
+            store: self.tree.storage.as_ref(),
+            codec: self.tree.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.tree.cache_stats,
+        };
+        let reader = NodeCachingReader {
+            inner: &inner,
+            nodes: &self.nodes,
+        };
+        let tree = JellyfishMerkleTree::new(&reader);
+        let (data, proof) = tree.get_with_proof(self.root_hash, key.key_hash())?;
+        Ok((data.map(|b| b.into()), proof))
+    }
+}
+
+/// Node reads served by the in-memory cache (`hits`) or the underline storage (`misses`).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Proof cache keyed by `(root_hash, key_hash)`.
+type ProofCache = LruCache<(HashValue, HashValue), (Option<Blob>, SparseMerkleProof)>;
+
+/// Negative cache of absent keys, keyed by `(root_hash, key_hash)`.
+type NegativeCache = LruCache<(HashValue, HashValue), ()>;
+
 //TODO remove the Lock.
 pub struct StateTree<K: RawKey> {
     storage: Arc<dyn StateNodeStore>,
+    codec: Arc<dyn NodeCodec<K>>,
     storage_root_hash: RwLock<HashValue>,
     updates: RwLock<BTreeMap<K, Option<Blob>>>,
-    cache: Mutex<StateCache<K>>,
+    cache: RwLock<StateCache<K>>,
+    proof_cache: Mutex<Option<ProofCache>>,
+    proof_cache_hits: AtomicU64,
+    negative_cache: Mutex<Option<NegativeCache>>,
+    negative_cache_hits: AtomicU64,
+    cache_stats: CacheCounters,
+    /// Flushed roots from oldest to newest, with the node keys become stale at each root.
+    /// None unless `enable_pruning` is called, so `flush` keeps nothing by default.
+    flush_history: Mutex<Option<VecDeque<(HashValue, Vec<NodeKey>)>>>,
+    /// Commit and flush in `put_with_auto_flush` once the pending count exceeds it,
+    /// it's kept by `clone`.
+    max_pending_nodes: Option<usize>,
+    missing_node_policy: RwLock<MissingNodePolicy>,
+    /// Leaf count at the current root, None if unknown, it's counted on the first read.
+    num_leaves: Mutex<Option<u64>>,
 }
 
 impl<K> Clone for StateTree<K>
@@ -88,7 +311,13 @@ where
     K: RawKey,
 {
     fn clone(&self) -> Self {
-        StateTree::new(self.storage.clone(), Some(*self.storage_root_hash.read()))
+        let mut tree = StateTree::with_node_codec(
+            self.storage.clone(),
+            Some(*self.storage_root_hash.read()),
+            self.codec.clone(),
+        );
+        tree.max_pending_nodes = self.max_pending_nodes;
+        tree
     }
 }
 
@@ -98,20 +327,177 @@ where
 {
     /// Construct a new state_db from provided `state_root_hash` with underline `state_storage`
     pub fn new(state_storage: Arc<dyn StateNodeStore>, state_root_hash: Option<HashValue>) -> Self {
+        Self::with_node_codec(state_storage, state_root_hash, Arc::new(DefaultNodeCodec))
+    }
+
+    /// Same as `new`, but nodes are encoded to and decoded from `state_storage` by `codec`.
+    pub fn with_node_codec(
+        state_storage: Arc<dyn StateNodeStore>,
+        state_root_hash: Option<HashValue>,
+        codec: Arc<dyn NodeCodec<K>>,
+    ) -> Self {
         let state_root_hash = state_root_hash.unwrap_or(*SPARSE_MERKLE_PLACEHOLDER_HASH);
         Self {
             storage: state_storage,
+            codec,
             storage_root_hash: RwLock::new(state_root_hash),
             updates: RwLock::new(BTreeMap::new()),
-            cache: Mutex::new(StateCache::new(state_root_hash)),
+            cache: RwLock::new(StateCache::new(state_root_hash)),
+            proof_cache: Mutex::new(None),
+            proof_cache_hits: AtomicU64::new(0),
+            negative_cache: Mutex::new(None),
+            negative_cache_hits: AtomicU64::new(0),
+            cache_stats: CacheCounters::default(),
+            flush_history: Mutex::new(None),
+            max_pending_nodes: None,
+            missing_node_policy: RwLock::new(MissingNodePolicy::default()),
+            num_leaves: Mutex::new(if state_root_hash == *SPARSE_MERKLE_PLACEHOLDER_HASH {
+                Some(0)
+            } else {
+                None
+            }),
         }
     }
 
+    /// Construct an empty state tree backed by an in-memory node store,
+    /// nodes are never written to a persistent store and are discarded on drop.
+    pub fn new_in_memory() -> Self {
+        Self::new(Arc::new(MockStateNodeStore::new()), None)
+    }
+
+    /// Same as `new`, but `put_with_auto_flush` commits and flushes the tree once the count of
+    /// staged updates plus unflushed nodes exceeds `max_pending_nodes`, to bound the memory
+    /// used by bulk import. Only `put_with_auto_flush` honours the limit, `put` and the other
+    /// update methods never flush. The limit is kept by `clone`.
+    pub fn new_auto_flush(
+        state_storage: Arc<dyn StateNodeStore>,
+        state_root_hash: Option<HashValue>,
+        max_pending_nodes: usize,
+    ) -> Self {
+        let mut tree = Self::new(state_storage, state_root_hash);
+        tree.max_pending_nodes = Some(max_pending_nodes);
+        tree
+    }
+
+    /// Same as `new`, the `state_root_hash` is not checked until the first access.
+    pub fn new_lazy(
+        state_storage: Arc<dyn StateNodeStore>,
+        state_root_hash: Option<HashValue>,
+    ) -> Self {
+        Self::new(state_storage, state_root_hash)
+    }
+
+    /// Open a state tree from provided `state_root_hash` with underline `state_storage`,
+    /// if `validate_on_open` is true, check the root node exists in storage eagerly.
+    pub fn open(
+        state_storage: Arc<dyn StateNodeStore>,
+        state_root_hash: Option<HashValue>,
+        validate_on_open: bool,
+    ) -> Result<Self> {
+        if validate_on_open {
+            if let Some(root_hash) = state_root_hash {
+                if root_hash != *SPARSE_MERKLE_PLACEHOLDER_HASH
+                    && state_storage.get(&root_hash)?.is_none()
+                {
+                    return Err(StateTreeError::MissingNode {
+                        node_hash: root_hash,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(Self::new(state_storage, state_root_hash))
+    }
+
+    /// Open a state tree at the root most recently recorded into `state_storage` by
+    /// `flush_and_record_root` or `record_latest_root`, or an empty tree if none is recorded.
+    /// A plain `flush` doesn't record the root, so trees sharing a store, like the account
+    /// storage trees, don't overwrite it.
+    pub fn open_latest(state_storage: Arc<dyn StateNodeStore>) -> Result<Self> {
+        let latest_root = state_storage.get_latest_root()?;
+        Self::open(state_storage, latest_root, true)
+    }
+
+    /// Set how `get` handles a node missing in storage.
+    pub fn set_missing_node_policy(&self, policy: MissingNodePolicy) {
+        *self.missing_node_policy.write() = policy;
+    }
+
+    /// Enable a proof cache holding at most `capacity` proofs.
+    /// Cached proofs are served by `get_with_proof` while the root is unchanged,
+    /// and the cache is cleared when a new root is committed.
+    pub fn enable_proof_cache(&self, capacity: usize) {
+        *self.proof_cache.lock() = Some(LruCache::new(capacity));
+    }
+
+    /// Track the roots flushed from now on, with the nodes become stale at each of them,
+    /// for `prune_keep_last`, `prune` and `compact`. The current root is the oldest tracked one.
+    /// The history grows on every flush until it's pruned, so only enable it if you prune.
+    /// The node store must be dedicated to this tree. Nodes are keyed by their hash, so trees on
+    /// a shared store, like the global state tree and the account storage trees, share the
+    /// nodes of identical subtrees, and pruning one tree would remove nodes other trees still
+    /// read. Never enable it on a shared store.
+    pub fn enable_pruning(&self) {
+        let mut history = self.flush_history.lock();
+        if history.is_none() {
+            let root_hash = *self.storage_root_hash.read();
+            *history = Some(VecDeque::from(vec![(root_hash, vec![])]));
+        }
+    }
+
+    /// How many `get_with_proof` calls were served from the proof cache.
+    pub fn proof_cache_hits(&self) -> u64 {
+        self.proof_cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Enable a negative cache remembering at most `capacity` absent keys.
+    /// `get` on a remembered key returns `None` without walking the tree while the root is
+    /// unchanged, and the cache is cleared when a new root is committed.
+    pub fn enable_negative_cache(&self, capacity: usize) {
+        *self.negative_cache.lock() = Some(LruCache::new(capacity));
+    }
+
+    /// How many `get` calls were served from the negative cache.
+    pub fn negative_cache_hits(&self) -> u64 {
+        self.negative_cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Node read stats of the in-memory cache since creation or the last `reset_cache_stats`.
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.cache_stats.hits.load(Ordering::Relaxed),
+            misses: self.cache_stats.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_cache_stats(&self) {
+        self.cache_stats.hits.store(0, Ordering::Relaxed);
+        self.cache_stats.misses.store(0, Ordering::Relaxed);
+    }
+
     /// get current root hash
     /// if any modification is not committed into state tree, the root hash is not changed.
     /// You can use `commit` to make current modification committed into local state tree.
     pub fn root_hash(&self) -> HashValue {
-        self.cache.lock().root_hash
+        self.cache.read().root_hash
+    }
+
+    /// Compute the root hash the next `commit` would produce, without committing.
+    /// The uncommitted modification and the local cache are left untouched, and no change set
+    /// is recorded, so it's cheap to drop the modification afterwards.
+    pub fn pending_root_hash(&self) -> Result<HashValue> {
+        let updates = self
+            .updates
+            .read()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        let cache_guard = self.cache.read();
+        if updates.is_empty() {
+            return Ok(cache_guard.root_hash);
+        }
+        let (new_state_root, _) = self.compute_updates(&cache_guard, updates)?;
+        Ok(new_state_root)
     }
 
     /// put a kv pair into tree.
@@ -119,10 +505,50 @@ where
     /// and use it as the `key_hash`.
     /// this will not compute new root hash,
     /// Use `commit` to recompute the root hash.
+    /// It never flushes, use `put_with_auto_flush` on a tree created by `new_auto_flush`.
     pub fn put(&self, key: K, value: Vec<u8>) {
         self.updates.write().insert(key, Some(value.into()));
     }
 
+    /// put a batch of kv pairs into tree, same as calling `put` for each pair,
+    /// but take the updates lock only once.
+    /// The pairs are staged, and applied in one tree traversal by the next `commit`.
+    pub fn put_all<I: IntoIterator<Item = (K, Vec<u8>)>>(&self, items: I) {
+        self.updates
+            .write()
+            .extend(items.into_iter().map(|(k, v)| (k, Some(v.into()))));
+    }
+
+    /// Same as `put`, but commit and flush the tree if it's created by `new_auto_flush`
+    /// and the pending count exceeds the limit, return true if an auto flush happened.
+    /// It's a separate method rather than `put` itself, because a flush can fail and writes the
+    /// storage, while `put` is infallible and only stages the change, and all the callers of
+    /// `put`, like the chain state db, rely on nothing being written before their own flush.
+    pub fn put_with_auto_flush(&self, key: K, value: Vec<u8>) -> Result<bool> {
+        self.put(key, value);
+        let max_pending_nodes = match self.max_pending_nodes {
+            Some(max_pending_nodes) => max_pending_nodes,
+            None => return Ok(false),
+        };
+        let pending = self
+            .updates
+            .read()
+            .len()
+            .saturating_add(self.cache.read().change_set.node_batch.len());
+        if pending <= max_pending_nodes {
+            return Ok(false);
+        }
+        self.commit()?;
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// put a kv pair into tree, same as `put`, but take the value as `Blob` directly.
+    pub fn put_blob(&self, key: K, value: Blob) -> Result<()> {
+        self.updates.write().insert(key, Some(value));
+        Ok(())
+    }
+
     /// Remove key_hash's data.
     /// this will not compute new root hash,
     /// Use `commit` to recompute the root hash.
@@ -137,32 +563,391 @@ where
         if let Some(uncomputed) = updates_guard.get(key).cloned() {
             return Ok(uncomputed.map(|b| b.into()));
         }
-        Ok(self.get_with_proof(key)?.0)
+        let cur_root_hash = self.root_hash();
+        let key_hash = key.key_hash();
+        if let Some(negative_cache) = self.negative_cache.lock().as_mut() {
+            if negative_cache.get(&(cur_root_hash, key_hash)).is_some() {
+                self.negative_cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        }
+        if *self.missing_node_policy.read() == MissingNodePolicy::TreatAsAbsent {
+            return self.get_missing_as_absent(cur_root_hash, key_hash);
+        }
+        let data = self.get_with_proof(key)?.0;
+        if data.is_none() {
+            if let Some(negative_cache) = self.negative_cache.lock().as_mut() {
+                negative_cache.put((cur_root_hash, key_hash), ());
+            }
+        }
+        Ok(data)
+    }
+
+    /// Read `key_hash` at `root_hash`, return `None` if a node on the path is missing.
+    fn get_missing_as_absent(
+        &self,
+        root_hash: HashValue,
+        key_hash: HashValue,
+    ) -> Result<Option<Vec<u8>>> {
+        let cache_guard = self.cache.read();
+        let inner = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.cache_stats,
+        };
+        let reader = MissingNodeRecorder {
+            inner: &inner,
+            missing: AtomicBool::new(false),
+        };
+        let tree = JellyfishMerkleTree::new(&reader);
+        match tree.get_with_proof(root_hash, key_hash) {
+            Ok((data, _)) => Ok(data.map(|b| b.into())),
+            Err(_) if reader.missing.load(Ordering::Relaxed) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn contains(&self, key: &K) -> Result<bool> {
         self.get(key).map(|result| result.is_some())
     }
 
+    /// Same as `contains`, but walk to the leaf and only compare the key,
+    /// the value is not copied out.
+    /// This will also read un-committed modification.
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        if let Some(uncomputed) = self.updates.read().get(key) {
+            return Ok(uncomputed.is_some());
+        }
+        let cache_guard = self.cache.read();
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.cache_stats,
+        };
+        let key_hash = key.key_hash();
+        let key_bytes = key_hash.to_vec();
+        let mut node_key = cache_guard.root_hash;
+        for depth in 0..=HashValue::LENGTH * 2 {
+            match reader.get_node(&node_key)? {
+                Node::Internal(internal_node) => {
+                    let byte = *key_bytes
+                        .get(depth / 2)
+                        .ok_or_else(|| format_err!("Ran out of nibbles of key {}.", key_hash))?;
+                    let nibble = if depth % 2 == 0 {
+                        byte >> 4
+                    } else {
+                        byte & 0x0f
+                    };
+                    match internal_node.child(Nibble::from(nibble)) {
+                        Some(child) => node_key = child.hash,
+                        None => return Ok(false),
+                    }
+                }
+                Node::Leaf(leaf_node) => return Ok(leaf_node.raw_key().key_hash() == key_hash),
+                Node::Null => return Ok(false),
+            }
+        }
+        Err(format_err!(
+            "State tree is deeper than the key {} path.",
+            key_hash
+        ))
+    }
+
     /// return value with it proof.
     /// NOTICE: this will only read from state tree.
     /// Any un-committed modification will not visible to the method.
     pub fn get_with_proof(&self, key: &K) -> Result<(Option<Vec<u8>>, SparseMerkleProof)> {
-        let mut cache_guard = self.cache.lock();
-        let cache = cache_guard.deref_mut();
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
         let cur_root_hash = cache.root_hash;
+        let key_hash = key.key_hash();
+        if let Some(proof_cache) = self.proof_cache.lock().as_mut() {
+            if let Some((data, proof)) = proof_cache.get(&(cur_root_hash, key_hash)) {
+                self.proof_cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok((data.clone().map(|b| b.into()), proof.clone()));
+            }
+        }
         let reader = CachedTreeReader {
             store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
             cache,
+            stats: &self.cache_stats,
         };
         let tree = JellyfishMerkleTree::new(&reader);
-        let (data, proof) = tree.get_with_proof(cur_root_hash, key.key_hash())?;
+        let (data, proof) = tree.get_with_proof(cur_root_hash, key_hash)?;
+        if let Some(proof_cache) = self.proof_cache.lock().as_mut() {
+            proof_cache.put((cur_root_hash, key_hash), (data.clone(), proof.clone()));
+        }
         match data {
             Some(b) => Ok((Some(b.into()), proof)),
             None => Ok((None, proof)),
         }
     }
 
+    /// Same as `get_with_proof`, but prove `key` against a historical `root` instead of the
+    /// current root, as a tree opened with `StateTree::new(store, Some(root))` does.
+    /// Error with `StateTreeError::MissingNode` if the nodes of `root` are pruned.
+    /// NOTICE: this will only read from state tree.
+    pub fn get_with_proof_at_root(
+        &self,
+        key: &K,
+        root: HashValue,
+    ) -> Result<(Option<Blob>, SparseMerkleProof)> {
+        let cache_guard = self.cache.read();
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.cache_stats,
+        };
+        JellyfishMerkleTree::new(&reader).get_with_proof(root, key.key_hash())
+    }
+
+    /// Same as `get_with_proof` for each of `keys`, but share the nodes read on the common
+    /// path prefixes, so each node is read and decoded at most once.
+    /// Every proof is independent, and verifies against the current root on its own.
+    /// NOTICE: this will only read from state tree.
+    pub fn get_many_with_proof(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<(Option<Vec<u8>>, SparseMerkleProof)>> {
+        let cache_guard = self.cache.read();
+        let cur_root_hash = cache_guard.root_hash;
+        let inner = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.cache_stats,
+        };
+        let nodes = Mutex::new(HashMap::new());
+        let reader = NodeCachingReader {
+            inner: &inner,
+            nodes: &nodes,
+        };
+        let tree = JellyfishMerkleTree::new(&reader);
+        keys.iter()
+            .map(|key| {
+                let (data, proof) = tree.get_with_proof(cur_root_hash, key.key_hash())?;
+                Ok((data.map(|b| b.into()), proof))
+            })
+            .collect()
+    }
+
+    /// Create a `ProofServer` bound to the current root.
+    pub fn proof_server(&self) -> ProofServer<'_, K> {
+        ProofServer {
+            tree: self,
+            root_hash: self.root_hash(),
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Same as `get_with_proof`, but return the value as `Blob` with the kind of the proof.
+    /// NOTICE: this will only read from state tree.
+    pub fn get_with_proof_typed(
+        &self,
+        key: &K,
+    ) -> Result<(Option<Blob>, SparseMerkleProof, ProofKind)> {
+        let (data, proof) = self.get_with_proof(key)?;
+        let kind = if data.is_some() {
+            ProofKind::Inclusion
+        } else {
+            ProofKind::Exclusion
+        };
+        Ok((data.map(Blob::from), proof, kind))
+    }
+
+    /// return the sibling hashes along `key`'s path, ordered from root to leaf.
+    /// It's the raw siblings of `get_with_proof`, without the leaf.
+    /// NOTICE: this will only read from state tree.
+    pub fn sibling_path(&self, key: &K) -> Result<Vec<HashValue>> {
+        let (_, proof) = self.get_with_proof(key)?;
+        Ok(proof.siblings().iter().rev().copied().collect())
+    }
+
+    /// return at most `limit` entries with key hash greater than `start_exclusive`'s,
+    /// in key hash order, and a range proof for the last entry.
+    /// The range proof covers all leaves from the leftmost one, so a page is verified together with
+    /// all the previous pages, see `SparseMerkleRangeProof::verify`.
+    /// NOTICE: this will only read from state tree.
+    pub fn get_range_with_proof(
+        &self,
+        start_exclusive: Option<&K>,
+        limit: usize,
+    ) -> Result<(Vec<(K, Blob)>, SparseMerkleRangeProof)> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let cur_root_hash = cache.root_hash;
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        let start_key_hash = start_exclusive.map(|key| key.key_hash());
+        let iterator = JellyfishMerkleIterator::new(
+            &reader,
+            cur_root_hash,
+            start_key_hash.unwrap_or_else(HashValue::zero),
+        )?;
+        let mut entries = vec![];
+        for item in iterator {
+            if entries.len() >= limit {
+                break;
+            }
+            let (key, blob) = item?;
+            if Some(key.key_hash()) == start_key_hash {
+                continue;
+            }
+            entries.push((key, blob));
+        }
+        let proof = match entries.last() {
+            Some((key, _)) => {
+                JellyfishMerkleTree::new(&reader).get_range_proof(cur_root_hash, key.key_hash())?
+            }
+            None => SparseMerkleRangeProof::new(vec![]),
+        };
+        Ok((entries, proof))
+    }
+
+    /// return the keys whose values differ between `from_root` and `to_root`, in key hash order,
+    /// including added and removed keys. Only the differing subtrees are walked.
+    /// NOTICE: this will only read from state tree.
+    pub fn changed_keys_between(&self, from_root: HashValue, to_root: HashValue) -> Result<Vec<K>> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        let mut keys = vec![];
+        diff_subtrees(&reader, from_root, to_root, &mut |from_leaf, to_leaf| {
+            if let Some(leaf) = to_leaf.or(from_leaf) {
+                keys.push(leaf.raw_key().clone());
+            }
+        })?;
+        Ok(keys)
+    }
+
+    /// return `(key, old, new)` for every key whose value differs between the current root and
+    /// `other_root`, in key hash order, `old` is the value at the current root, `new` is the value
+    /// at `other_root`, None means the key is absent. Subtrees with the same hash are skipped.
+    /// NOTICE: this will only read from state tree.
+    pub fn diff(&self, other_root: HashValue) -> Result<Vec<(K, Option<Blob>, Option<Blob>)>> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let cur_root_hash = cache.root_hash;
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        let mut changes = vec![];
+        diff_subtrees(
+            &reader,
+            cur_root_hash,
+            other_root,
+            &mut |old_leaf, new_leaf| {
+                if let Some(leaf) = new_leaf.or(old_leaf) {
+                    changes.push((
+                        leaf.raw_key().clone(),
+                        old_leaf.map(|leaf| leaf.blob().clone()),
+                        new_leaf.map(|leaf| leaf.blob().clone()),
+                    ));
+                }
+            },
+        )?;
+        Ok(changes)
+    }
+
+    /// Make a `StateDiff` from `from_root` to the current root.
+    /// NOTICE: this will only read from state tree.
+    pub fn state_diff(&self, from_root: HashValue) -> Result<StateDiff<K>> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let to_root = cache.root_hash;
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        let mut changes = vec![];
+        diff_subtrees(
+            &reader,
+            from_root,
+            to_root,
+            &mut |from_leaf, to_leaf| match (from_leaf, to_leaf) {
+                (_, Some(leaf)) => {
+                    changes.push((leaf.raw_key().clone(), Some(leaf.blob().clone())))
+                }
+                (Some(leaf), None) => changes.push((leaf.raw_key().clone(), None)),
+                (None, None) => {}
+            },
+        )?;
+        Ok(StateDiff {
+            from_root,
+            to_root,
+            changes,
+        })
+    }
+
+    /// Apply a `StateDiff` to this tree, the current root must be the `from_root` of the diff.
+    pub fn apply_diff(&self, diff: &StateDiff<K>) -> Result<HashValue> {
+        ensure!(
+            !self.is_dirty(),
+            "Can not apply state diff to state tree with uncommitted changes."
+        );
+        let cur_root_hash = self.root_hash();
+        ensure!(
+            cur_root_hash == diff.from_root,
+            "State diff is from root {}, but current root is {}.",
+            diff.from_root,
+            cur_root_hash
+        );
+        let root_hash = self.updates(diff.changes.clone())?;
+        ensure!(
+            root_hash == diff.to_root,
+            "Root hash after apply state diff mismatch, expect {}, got {}.",
+            diff.to_root,
+            root_hash
+        );
+        Ok(root_hash)
+    }
+
+    /// A cheap estimate of the diff size between current root and `other_root`,
+    /// it's the number of differing top-level subtrees, leaves are not walked.
+    /// NOTICE: this will only read from state tree.
+    pub fn diff_cost_estimate(&self, other_root: HashValue) -> Result<usize> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let cur_root_hash = cache.root_hash;
+        if cur_root_hash == other_root {
+            return Ok(0);
+        }
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        match (
+            reader.get_node(&cur_root_hash)?,
+            reader.get_node(&other_root)?,
+        ) {
+            (Node::Internal(cur), Node::Internal(other)) => Ok((0..16u8)
+                .map(Nibble::from)
+                .filter(|n| cur.child(*n).map(|c| c.hash) != other.child(*n).map(|c| c.hash))
+                .count()),
+            _ => Ok(1),
+        }
+    }
+
     /// Commit current modification into state tree's local cache,
     /// and return new root hash.
     /// NOTICE: this method will not flush the changes into disk.
@@ -178,6 +963,264 @@ where
         Ok(new_root_hash)
     }
 
+    /// Same as `commit`, but error if the new root is one of `forbidden`, to catch accidental
+    /// reverts to a prior state. On error nothing is committed and the modification is kept.
+    pub fn commit_not_in(&self, forbidden: &[HashValue]) -> Result<HashValue> {
+        let mut guard = self.updates.write();
+        let updates = guard
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        let mut cache_guard = self.cache.write();
+        let cache = cache_guard.deref_mut();
+        let change = if updates.is_empty() {
+            None
+        } else {
+            Some(self.compute_updates(cache, updates)?)
+        };
+        let new_root_hash = change
+            .as_ref()
+            .map(|(root_hash, _)| *root_hash)
+            .unwrap_or(cache.root_hash);
+        ensure!(
+            !forbidden.contains(&new_root_hash),
+            "New state root {} is forbidden.",
+            new_root_hash
+        );
+        if let Some((root_hash, change_set)) = change {
+            self.apply_updates(cache, root_hash, change_set);
+        }
+        guard.clear();
+        Ok(new_root_hash)
+    }
+
+    /// Same as `commit`, but also return the nodes to persist for the new root, which are all the
+    /// nodes committed since the last flush, so the batch can be written by another thread with
+    /// `apply_batch` while the execution continues. The tree keeps serving the nodes from its
+    /// local cache until `flush`, writing them twice is harmless.
+    pub fn commit_to_batch(&self) -> Result<(HashValue, NodeBatch<K>)> {
+        let mut guard = self.updates.write();
+        let updates = guard
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        let mut cache_guard = self.cache.write();
+        let cache = cache_guard.deref_mut();
+        if !updates.is_empty() {
+            let (new_root_hash, change_set) = self.compute_updates(cache, updates)?;
+            self.apply_updates(cache, new_root_hash, change_set);
+        }
+        guard.clear();
+        Ok((cache.root_hash, cache.change_set.node_batch.clone()))
+    }
+
+    /// Write a batch returned by `commit_to_batch` to the storage, and check `root_hash` is
+    /// present. The latest root is not recorded, call `record_latest_root` after it, so if it
+    /// crashes in between, the latest root in storage is still a complete older one, and the batch
+    /// can be made again by re-executing from there. The local cache of this tree is not changed.
+    pub fn apply_batch(&self, root_hash: HashValue, batch: NodeBatch<K>) -> Result<()> {
+        let mut node_map = BTreeMap::new();
+        for (nk, n) in batch.iter() {
+            node_map.insert(*nk, self.codec.encode(n)?);
+        }
+        self.storage.write_nodes(node_map)?;
+        self.ensure_root_stored(root_hash)
+    }
+
+    /// Record `root_hash` as the latest root of the storage, for `open_latest`.
+    /// The root must be written to the storage already.
+    pub fn record_latest_root(&self, root_hash: HashValue) -> Result<()> {
+        self.ensure_root_stored(root_hash)?;
+        self.storage.save_latest_root(root_hash)
+    }
+
+    fn ensure_root_stored(&self, root_hash: HashValue) -> Result<()> {
+        if root_hash != *SPARSE_MERKLE_PLACEHOLDER_HASH && self.storage.get(&root_hash)?.is_none() {
+            return Err(StateTreeError::MissingNode {
+                node_hash: root_hash,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Same as `commit`, but also return the hashes of the nodes that became stale in this
+    /// commit, so an external pruner can schedule their deletion.
+    pub fn commit_with_stale(&self) -> Result<(HashValue, Vec<HashValue>)> {
+        let mut guard = self.updates.write();
+        let updates = guard
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        let mut cache_guard = self.cache.write();
+        let cache = cache_guard.deref_mut();
+        if updates.is_empty() {
+            return Ok((cache.root_hash, vec![]));
+        }
+        let (new_root_hash, change_set) = self.compute_updates(cache, updates)?;
+        let stale_node_keys = change_set
+            .stale_node_index_batch
+            .iter()
+            .map(|index| index.node_key)
+            .filter(|node_key| node_key != &*SPARSE_MERKLE_PLACEHOLDER_HASH)
+            .collect();
+        self.apply_updates(cache, new_root_hash, change_set);
+        guard.clear();
+        Ok((new_root_hash, stale_node_keys))
+    }
+
+    /// Same as `commit`, but also return the manifest of nodes created by this commit,
+    /// as `(node_hash, encoded_node)` pairs. A replica which has applied the previous manifests
+    /// can reproduce the new root by `apply_change_set`.
+    pub fn commit_with_manifest(&self) -> Result<(HashValue, Vec<(HashValue, Vec<u8>)>)> {
+        let existed_nodes: HashSet<NodeKey> = self
+            .cache
+            .read()
+            .change_set
+            .node_batch
+            .keys()
+            .copied()
+            .collect();
+        let new_root_hash = self.commit()?;
+        let manifest = self
+            .cache
+            .read()
+            .change_set
+            .node_batch
+            .iter()
+            .filter(|(node_key, _)| !existed_nodes.contains(node_key))
+            .map(|(node_key, node)| Ok((*node_key, self.codec.encode(node)?.0)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((new_root_hash, manifest))
+    }
+
+    /// Write the nodes of a manifest from `commit_with_manifest` into storage,
+    /// and advance the tree to `root_hash`. Every node is checked against its hash.
+    pub fn apply_change_set(
+        &self,
+        root_hash: HashValue,
+        manifest: Vec<(HashValue, Vec<u8>)>,
+    ) -> Result<()> {
+        ensure!(
+            !self.is_dirty(),
+            "Can not apply change set to state tree with uncommitted changes."
+        );
+        let mut node_map = BTreeMap::new();
+        for (node_key, bytes) in manifest {
+            let node = StateNode(bytes);
+            let node_hash = self.codec.decode(node.clone())?.hash();
+            ensure!(
+                node_hash == node_key,
+                "Node hash {} mismatch with manifest key {}.",
+                node_hash,
+                node_key
+            );
+            node_map.insert(node_key, node);
+        }
+        self.storage.write_nodes(node_map)?;
+        self.ensure_root_stored(root_hash)?;
+        *self.storage_root_hash.write() = root_hash;
+        self.cache.write().reset(root_hash);
+        self.clear_read_caches();
+        // recount the leaves of the new root lazily
+        *self.num_leaves.lock() = if root_hash == *SPARSE_MERKLE_PLACEHOLDER_HASH {
+            Some(0)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    /// Same as `commit`, but also return an `UndoLog` with the prior values of changed keys,
+    /// which can be passed to `apply_undo` to roll back this commit.
+    pub fn commit_with_undo(&self) -> Result<(HashValue, UndoLog<K>)> {
+        let mut guard = self.updates.write();
+        let prev_root_hash = self.root_hash();
+        let prev_values = guard
+            .keys()
+            .map(|k| Ok((k.clone(), self.get_with_proof(k)?.0.map(Blob::from))))
+            .collect::<Result<Vec<_>>>()?;
+        let updates = guard
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        let new_root_hash = self.updates(updates)?;
+        guard.clear();
+        Ok((
+            new_root_hash,
+            UndoLog {
+                prev_root_hash,
+                root_hash: new_root_hash,
+                prev_values,
+            },
+        ))
+    }
+
+    /// Take a snapshot of the local cache and the uncommitted modification,
+    /// the persistent storage is not included.
+    pub fn snapshot(&self) -> StateTreeSnapshot<K> {
+        let updates = self.updates.read();
+        let cache = self.cache.read();
+        StateTreeSnapshot {
+            storage_root_hash: *self.storage_root_hash.read(),
+            cache: cache.clone(),
+            updates: updates.clone(),
+            num_leaves: *self.num_leaves.lock(),
+        }
+    }
+
+    /// Restore the local cache and the uncommitted modification to `snapshot`, the commits and
+    /// puts after it are dropped. The persistent storage is not touched, so error if the tree
+    /// is flushed after the snapshot is made.
+    pub fn rollback_to(&self, snapshot: StateTreeSnapshot<K>) -> Result<()> {
+        let mut updates = self.updates.write();
+        let mut cache = self.cache.write();
+        let storage_root_hash = *self.storage_root_hash.read();
+        ensure!(
+            storage_root_hash == snapshot.storage_root_hash,
+            "Can not rollback to snapshot of storage root {}, the tree is flushed to {}.",
+            snapshot.storage_root_hash,
+            storage_root_hash
+        );
+        *cache = snapshot.cache;
+        *updates = snapshot.updates;
+        *self.num_leaves.lock() = snapshot.num_leaves;
+        self.clear_read_caches();
+        Ok(())
+    }
+
+    /// Revert the commit recorded by `undo`, the tree must be at the undo's root and clean.
+    /// Return the restored root hash, which is the root before the commit.
+    pub fn apply_undo(&self, undo: UndoLog<K>) -> Result<HashValue> {
+        ensure!(
+            !self.is_dirty(),
+            "Can not apply undo log to state tree with uncommitted changes."
+        );
+        let cur_root_hash = self.root_hash();
+        ensure!(
+            cur_root_hash == undo.root_hash,
+            "Undo log is for root {}, but current root is {}.",
+            undo.root_hash,
+            cur_root_hash
+        );
+        let root_hash = self.updates(undo.prev_values)?;
+        ensure!(
+            root_hash == undo.prev_root_hash,
+            "Root hash after undo mismatch, expect {}, got {}.",
+            undo.prev_root_hash,
+            root_hash
+        );
+        Ok(root_hash)
+    }
+
+    /// Same as `commit`, but also return the wall-clock duration of the commit.
+    #[cfg(feature = "metered")]
+    pub fn commit_timed(&self) -> Result<(HashValue, std::time::Duration)> {
+        let start = std::time::Instant::now();
+        let root_hash = self.commit()?;
+        Ok((root_hash, start.elapsed()))
+    }
+
     /// check if there is data that has not been commit.
     pub fn is_dirty(&self) -> bool {
         self.updates.read().len() > 0
@@ -194,29 +1237,229 @@ where
         Ok(())
     }
 
+    /// Overlay `state_set` onto current tree, overlapping keys are overwritten,
+    /// then commit and return the new root hash.
+    pub fn apply_state_set(&self, state_set: StateSet) -> Result<HashValue> {
+        for (k, v) in state_set.iter() {
+            self.put(K::decode_key(k.as_slice())?, v.clone());
+        }
+        self.commit()
+    }
+
     /// commit the state change into underline storage.
     pub fn flush(&self) -> Result<()> {
         let (root_hash, change_sets) = self.change_sets();
 
         let mut node_map = BTreeMap::new();
         for (nk, n) in change_sets.node_batch.into_iter() {
-            node_map.insert(nk, n.try_into()?);
+            node_map.insert(nk, self.codec.encode(&n)?);
         }
         self.storage.write_nodes(node_map)?;
         // and then advance the storage root hash
         *self.storage_root_hash.write() = root_hash;
-        self.cache.lock().reset(root_hash);
+        self.cache.write().reset(root_hash);
+        self.clear_read_caches();
+        if let Some(history) = self.flush_history.lock().as_mut() {
+            if history.back().map(|(root, _)| *root) != Some(root_hash) {
+                let stale_node_keys = change_sets
+                    .stale_node_index_batch
+                    .into_iter()
+                    .map(|index| index.node_key)
+                    .filter(|node_key| node_key != &*SPARSE_MERKLE_PLACEHOLDER_HASH)
+                    .collect();
+                history.push_back((root_hash, stale_node_keys));
+            }
+        }
         Ok(())
     }
 
+    /// Same as `flush`, and then record the flushed root as the latest root of the storage,
+    /// for `open_latest`. Only the tree owning the store, like the global state tree, should
+    /// record it.
+    pub fn flush_and_record_root(&self) -> Result<()> {
+        self.flush()?;
+        self.storage.save_latest_root(self.root_hash())
+    }
+
+    /// Keep the most recent `n` flushed roots of this tree, and remove the stale nodes of older
+    /// roots from storage, return the count of nodes removed.
+    /// Only the roots flushed by this tree instance are tracked, the root when `enable_pruning` is
+    /// called is the oldest one. A stale node is only removed if it's not reachable from any
+    /// kept root, so reads against the kept roots never break, as long as the store is dedicated
+    /// to this tree, see `enable_pruning`.
+    pub fn prune_keep_last(&self, n: usize) -> Result<usize> {
+        ensure!(n > 0, "Must keep at least one state root.");
+        let mut guard = self.flush_history.lock();
+        let history = guard.as_mut().ok_or_else(pruning_not_enabled)?;
+        if history.len() <= n {
+            return Ok(0);
+        }
+        let drop_count = history.len().saturating_sub(n);
+        self.prune_history(history, drop_count)
+    }
+
+    /// Keep `stale_before_root` and the roots flushed after it, and remove the stale nodes of
+    /// the roots flushed before it from storage, return the count of nodes removed.
+    /// `stale_before_root` must be a root flushed by this tree instance, or the root when
+    /// `enable_pruning` is called. It's a snapshot at the call time, the kept roots stay
+    /// readable, the older ones become unreadable as soon as a node on their path is removed.
+    /// The store must be dedicated to this tree, see `enable_pruning`.
+    pub fn prune(&self, stale_before_root: HashValue) -> Result<usize> {
+        let mut guard = self.flush_history.lock();
+        let history = guard.as_mut().ok_or_else(pruning_not_enabled)?;
+        let drop_count = history
+            .iter()
+            .position(|(root, _)| *root == stale_before_root)
+            .ok_or_else(|| {
+                format_err!(
+                    "Can not prune before state root {}, it's not flushed by this tree.",
+                    stale_before_root
+                )
+            })?;
+        self.prune_history(history, drop_count)
+    }
+
+    /// Drop the oldest `drop_count` roots of `history`, and remove their stale nodes which
+    /// are not reachable from the remaining roots.
+    fn prune_history(
+        &self,
+        history: &mut VecDeque<(HashValue, Vec<NodeKey>)>,
+        drop_count: usize,
+    ) -> Result<usize> {
+        if drop_count == 0 {
+            return Ok(0);
+        }
+        let mut candidates = HashSet::new();
+        // The stale nodes recorded at version `i` belong to version `i - 1`.
+        for (_, stale_node_keys) in history.iter_mut().skip(1).take(drop_count) {
+            candidates.extend(stale_node_keys.drain(..));
+        }
+        history.drain(..drop_count);
+
+        let mut reachable = HashSet::new();
+        for (root, _) in history.iter() {
+            self.collect_node_keys(*root, &mut reachable)?;
+        }
+        let to_delete: Vec<NodeKey> = candidates
+            .into_iter()
+            .filter(|node_key| !reachable.contains(node_key))
+            .collect();
+        let removed = to_delete.len();
+        self.storage.delete_nodes(to_delete)?;
+        Ok(removed)
+    }
+
+    /// Collect keys of all the stored nodes reachable from `root`.
+    fn collect_node_keys(&self, root: HashValue, keys: &mut HashSet<NodeKey>) -> Result<()> {
+        let mut stack = vec![root];
+        while let Some(node_key) = stack.pop() {
+            if node_key == *SPARSE_MERKLE_PLACEHOLDER_HASH || !keys.insert(node_key) {
+                continue;
+            }
+            let node = self
+                .storage
+                .get(&node_key)?
+                .ok_or(StateTreeError::MissingNode {
+                    node_hash: node_key,
+                })?;
+            if let Node::Internal(internal_node) = self.codec.decode(node)? {
+                stack.extend(internal_node.all_child());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every node reachable from each of `roots` exists in storage and matches its hash,
+    /// return the result of each root in order.
+    /// Subtrees already verified by a previous root are not traversed again.
+    pub fn verify_reachable_multi(
+        &self,
+        roots: &[HashValue],
+    ) -> Result<Vec<(HashValue, Result<()>)>> {
+        let mut verified = HashSet::new();
+        let mut results = Vec::with_capacity(roots.len());
+        for root in roots {
+            let mut visited = HashSet::new();
+            let result = self.verify_reachable(*root, &verified, &mut visited);
+            if result.is_ok() {
+                verified.extend(visited);
+            }
+            results.push((*root, result));
+        }
+        Ok(results)
+    }
+
+    fn verify_reachable(
+        &self,
+        root: HashValue,
+        verified: &HashSet<NodeKey>,
+        visited: &mut HashSet<NodeKey>,
+    ) -> Result<()> {
+        let mut stack = vec![root];
+        while let Some(node_key) = stack.pop() {
+            if node_key == *SPARSE_MERKLE_PLACEHOLDER_HASH
+                || verified.contains(&node_key)
+                || !visited.insert(node_key)
+            {
+                continue;
+            }
+            let node = self
+                .storage
+                .get(&node_key)?
+                .ok_or(StateTreeError::MissingNode {
+                    node_hash: node_key,
+                })?;
+            let node = self.codec.decode(node)?;
+            let node_hash = node.hash();
+            ensure!(
+                node_hash == node_key,
+                "Corrupted node at {:?}, actual hash {:?}.",
+                node_key,
+                node_hash
+            );
+            if let Node::Internal(internal_node) = node {
+                stack.extend(internal_node.all_child());
+            }
+        }
+        Ok(())
+    }
+
+    /// The count of leaves at the current root, un-committed modification is not counted.
+    /// It's kept up to date on commit, for a tree opened with a non-empty root it's counted by
+    /// walking the tree on the first call.
+    pub fn num_leaves(&self) -> Result<u64> {
+        let cache_guard = self.cache.read();
+        let mut num_leaves = self.num_leaves.lock();
+        if let Some(num_leaves) = *num_leaves {
+            return Ok(num_leaves);
+        }
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache: &cache_guard,
+            stats: &self.cache_stats,
+        };
+        let iterator =
+            JellyfishMerkleIterator::new(&reader, cache_guard.root_hash, HashValue::zero())?;
+        let mut count = 0u64;
+        for item in iterator {
+            item?;
+            count = count.saturating_add(1);
+        }
+        *num_leaves = Some(count);
+        Ok(count)
+    }
+
     /// Dump tree to state set.
     pub fn dump(&self) -> Result<StateSet> {
         let cur_root_hash = self.root_hash();
-        let mut cache_guard = self.cache.lock();
-        let cache = cache_guard.deref_mut();
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
         let reader = CachedTreeReader {
             store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
             cache,
+            stats: &self.cache_stats,
         };
         let iterator = JellyfishMerkleIterator::new(&reader, cur_root_hash, HashValue::zero())?;
         let mut states = vec![];
@@ -227,15 +1470,39 @@ where
         Ok(StateSet::new(states))
     }
 
+    /// return at most `limit` entries in ascending `key_hash()` order, starting at `start`
+    /// inclusive, or at the first key if `start` is None.
+    /// The order is deterministic for a given root, so a caller can page through the tree by
+    /// passing the key after the last one it got, and resume after a restart.
+    /// NOTICE: this will only read from state tree.
+    pub fn iter_range(&self, start: Option<K>, limit: usize) -> Result<Vec<(K, Blob)>> {
+        let cache_guard = self.cache.read();
+        let cache = &*cache_guard;
+        let cur_root_hash = cache.root_hash;
+        let reader = CachedTreeReader {
+            store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
+            cache,
+            stats: &self.cache_stats,
+        };
+        let start_key_hash = start
+            .map(|key| key.key_hash())
+            .unwrap_or_else(HashValue::zero);
+        JellyfishMerkleIterator::new(&reader, cur_root_hash, start_key_hash)?
+            .take(limit)
+            .collect()
+    }
+
     pub fn dump_iter(&self) -> Result<JellyfishMerkleIntoIterator<K, StorageTreeReader<K>>> {
         let cur_root_hash = self.root_hash();
         let cache = {
-            let cache_guard = self.cache.lock();
+            let cache_guard = self.cache.read();
             cache_guard.clone()
         };
         let iterator = JellyfishMerkleIntoIterator::new(
             StorageTreeReader {
                 store: self.storage.clone(),
+                codec: self.codec.clone(),
                 cache,
             },
             cur_root_hash,
@@ -244,26 +1511,104 @@ where
         Ok(iterator)
     }
 
+    /// Rewrite the live nodes of the current root into a fresh tree and flush it, then remove the
+    /// stale nodes of the roots flushed by this tree instance from storage.
+    /// The tree is content-addressed, so the returned root equals the current `root_hash()`.
+    /// Pending commits are flushed first. Only the roots flushed by this instance are tracked,
+    /// stale nodes flushed by other instances stay in storage, and the older roots of this
+    /// instance become unreadable.
+    /// It requires `enable_pruning`, so the store must be dedicated to this tree, nodes still
+    /// read by other trees on a shared store would be removed otherwise.
+    pub fn compact(&self) -> Result<HashValue> {
+        ensure!(
+            !self.is_dirty(),
+            "Can not compact state tree with uncommitted changes."
+        );
+        if self.flush_history.lock().is_none() {
+            return Err(pruning_not_enabled());
+        }
+        self.flush()?;
+        let cur_root_hash = self.root_hash();
+        let entries = self
+            .dump_iter()?
+            .map(|item| item.map(|(k, v)| (k, Some(v))))
+            .collect::<Result<Vec<_>>>()?;
+        let fresh_tree =
+            StateTree::<K>::with_node_codec(self.storage.clone(), None, self.codec.clone());
+        let new_root_hash = fresh_tree.updates(entries)?;
+        ensure!(
+            new_root_hash == cur_root_hash,
+            "Compacted root {} mismatch with origin root {}",
+            new_root_hash,
+            cur_root_hash
+        );
+        fresh_tree.flush()?;
+        *self.storage_root_hash.write() = new_root_hash;
+        self.cache.write().reset(new_root_hash);
+        self.clear_read_caches();
+        let mut guard = self.flush_history.lock();
+        let history = guard.as_mut().ok_or_else(pruning_not_enabled)?;
+        let drop_count = history.len().saturating_sub(1);
+        self.prune_history(history, drop_count)?;
+        Ok(new_root_hash)
+    }
+
     /// passing None value with a key means delete the key
     fn updates(&self, updates: Vec<(K, Option<Blob>)>) -> Result<HashValue> {
-        let cur_root_hash = self.root_hash();
         //TODO should throw a error?
         if updates.is_empty() {
-            return Ok(cur_root_hash);
+            return Ok(self.root_hash());
         }
-        let mut cache_guard = self.cache.lock();
+        let mut cache_guard = self.cache.write();
         let cache = cache_guard.deref_mut();
+        let (new_state_root, change_set) = self.compute_updates(cache, updates)?;
+        self.apply_updates(cache, new_state_root, change_set);
+        Ok(new_state_root)
+    }
+
+    /// Compute the new root and change set of `updates` on top of `cache`, without applying them.
+    fn compute_updates(
+        &self,
+        cache: &StateCache<K>,
+        updates: Vec<(K, Option<Blob>)>,
+    ) -> Result<(HashValue, TreeUpdateBatch<K>)> {
         let reader = CachedTreeReader {
             store: self.storage.as_ref(),
+            codec: self.codec.as_ref(),
             cache,
+            stats: &self.cache_stats,
         };
         let tree = JellyfishMerkleTree::new(&reader);
-        let (new_state_root, change_set) = tree.updates(Some(cur_root_hash), updates)?;
+        tree.updates(Some(cache.root_hash), updates)
+    }
+
+    /// Apply a change set from `compute_updates` into `cache`.
+    fn apply_updates(
+        &self,
+        cache: &mut StateCache<K>,
+        new_state_root: HashValue,
+        change_set: TreeUpdateBatch<K>,
+    ) {
         // cache.root_hashes.push(new_state_root);
         // cache.change_sets.push(change_set);
         // cache.root_hash = new_state_root;
+        if let Some(num_leaves) = self.num_leaves.lock().as_mut() {
+            *num_leaves = num_leaves
+                .saturating_add(change_set.num_new_leaves as u64)
+                .saturating_sub(change_set.num_stale_leaves as u64);
+        }
         cache.add_changeset(new_state_root, change_set);
-        Ok(new_state_root)
+        self.clear_read_caches();
+    }
+
+    /// Clear the proof and negative caches, whenever the root or the local cache is changed.
+    fn clear_read_caches(&self) {
+        if let Some(proof_cache) = self.proof_cache.lock().as_mut() {
+            proof_cache.clear();
+        }
+        if let Some(negative_cache) = self.negative_cache.lock().as_mut() {
+            negative_cache.clear();
+        }
     }
 
     //
@@ -297,7 +1642,7 @@ where
 
     /// get all changes so far based on initial root_hash.
     pub fn change_sets(&self) -> (HashValue, TreeUpdateBatch<K>) {
-        let cache_guard = self.cache.lock();
+        let cache_guard = self.cache.read();
         (cache_guard.root_hash, cache_guard.change_set.clone())
     }
     // TODO: to keep atomic with other commit.
@@ -314,38 +1659,219 @@ where
     }
 }
 
+/// Verify each `(key, value, proof)` entry against `root`, the value None means the proof is
+/// an exclusion proof. Error with the index of the first invalid entry.
+pub fn verify_proofs(
+    root: HashValue,
+    entries: &[(HashValueKey, Option<Blob>, SparseMerkleProof)],
+) -> Result<()> {
+    for (index, (key, value, proof)) in entries.iter().enumerate() {
+        proof
+            .verify(root, key.key_hash(), value.as_ref())
+            .map_err(|e| {
+                format_err!(
+                    "Invalid proof at index {} for key {} against root {}: {}",
+                    index,
+                    key.key_hash(),
+                    root,
+                    e
+                )
+            })?;
+    }
+    Ok(())
+}
+
+/// Verify each `(root, key, value, proof)` item against its own root.
+/// This is useful when proofs come from different roots, for example during a reorg.
+pub fn verify_proofs_multi(
+    items: &[(HashValue, HashValueKey, Option<Blob>, SparseMerkleProof)],
+) -> Vec<Result<()>> {
+    items
+        .iter()
+        .map(|(root, key, value, proof)| proof.verify(*root, key.key_hash(), value.as_ref()))
+        .collect()
+}
+
+/// Walk the differing subtrees of `from` and `to` in tandem, and call `on_change` with
+/// `(from_leaf, to_leaf)` for every key whose value differs, in key hash order.
+/// Subtrees with the same hash are skipped.
+fn diff_subtrees<K, R, F>(reader: &R, from: NodeKey, to: NodeKey, on_change: &mut F) -> Result<()>
+where
+    K: RawKey,
+    R: TreeReader<K>,
+    F: FnMut(Option<&LeafNode<K>>, Option<&LeafNode<K>>),
+{
+    if from == to {
+        return Ok(());
+    }
+    match (reader.get_node(&from)?, reader.get_node(&to)?) {
+        (Node::Internal(from_node), Node::Internal(to_node)) => {
+            for nibble in (0..16u8).map(Nibble::from) {
+                let child_hash = |node: &InternalNode| {
+                    node.child(nibble)
+                        .map(|child| child.hash)
+                        .unwrap_or(*SPARSE_MERKLE_PLACEHOLDER_HASH)
+                };
+                diff_subtrees(
+                    reader,
+                    child_hash(&from_node),
+                    child_hash(&to_node),
+                    on_change,
+                )?;
+            }
+        }
+        (from_node, to_node) => {
+            let mut from_leaves = BTreeMap::new();
+            collect_leaves(reader, from_node, &mut from_leaves)?;
+            let mut to_leaves = BTreeMap::new();
+            collect_leaves(reader, to_node, &mut to_leaves)?;
+            let key_hashes: BTreeSet<HashValue> = from_leaves
+                .keys()
+                .chain(to_leaves.keys())
+                .copied()
+                .collect();
+            for key_hash in key_hashes {
+                let from_leaf = from_leaves.get(&key_hash);
+                let to_leaf = to_leaves.get(&key_hash);
+                if from_leaf.map(|leaf| leaf.blob_hash()) != to_leaf.map(|leaf| leaf.blob_hash()) {
+                    on_change(from_leaf, to_leaf);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect all the leaves of the subtree rooted at `node`, keyed by key hash.
+fn collect_leaves<K, R>(
+    reader: &R,
+    node: Node<K>,
+    leaves: &mut BTreeMap<HashValue, LeafNode<K>>,
+) -> Result<()>
+where
+    K: RawKey,
+    R: TreeReader<K>,
+{
+    match node {
+        Node::Null => {}
+        Node::Leaf(leaf) => {
+            leaves.insert(leaf.raw_key().key_hash(), leaf);
+        }
+        Node::Internal(internal) => {
+            for child in internal.all_child() {
+                collect_leaves(reader, reader.get_node(&child)?, leaves)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Unwrap the node read by `get_node_option`, error with `StateTreeError::MissingNode` if absent.
+fn node_or_missing<K: RawKey>(node: Option<Node<K>>, node_key: &NodeKey) -> Result<Node<K>> {
+    node.ok_or_else(|| {
+        StateTreeError::MissingNode {
+            node_hash: *node_key,
+        }
+        .into()
+    })
+}
+
 struct CachedTreeReader<'a, K: RawKey> {
     store: &'a dyn StateNodeStore,
+    codec: &'a dyn NodeCodec<K>,
     cache: &'a StateCache<K>,
+    stats: &'a CacheCounters,
 }
 
 impl<'a, K> TreeReader<K> for CachedTreeReader<'a, K>
 where
     K: RawKey,
 {
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node<K>> {
+        node_or_missing(self.get_node_option(node_key)?, node_key)
+    }
+
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node<K>>> {
         if node_key == &*SPARSE_MERKLE_PLACEHOLDER_HASH {
             return Ok(Some(Node::new_null()));
         }
         if let Some(n) = self.cache.change_set.node_batch.get(node_key).cloned() {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
             return Ok(Some(n));
         }
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
         match self.store.get(node_key) {
-            Ok(Some(n)) => Ok(Some(n.try_into()?)),
+            Ok(Some(n)) => Ok(Some(self.codec.decode(n)?)),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
     }
 }
 
+/// Record whether any node read from `inner` is missing.
+struct MissingNodeRecorder<'a, R> {
+    inner: &'a R,
+    missing: AtomicBool,
+}
+
+impl<'a, K, R> TreeReader<K> for MissingNodeRecorder<'a, R>
+where
+    K: RawKey,
+    R: TreeReader<K>,
+{
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node<K>> {
+        node_or_missing(self.get_node_option(node_key)?, node_key)
+    }
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node<K>>> {
+        let node = self.inner.get_node_option(node_key)?;
+        if node.is_none() {
+            self.missing.store(true, Ordering::Relaxed);
+        }
+        Ok(node)
+    }
+}
+
+/// Serve nodes from `nodes`, and keep the nodes read from `inner` there.
+struct NodeCachingReader<'a, K: RawKey, R> {
+    inner: &'a R,
+    nodes: &'a Mutex<HashMap<NodeKey, Node<K>>>,
+}
+
+impl<'a, K, R> TreeReader<K> for NodeCachingReader<'a, K, R>
+where
+    K: RawKey,
+    R: TreeReader<K>,
+{
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node<K>> {
+        node_or_missing(self.get_node_option(node_key)?, node_key)
+    }
+
+    fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node<K>>> {
+        if let Some(node) = self.nodes.lock().get(node_key) {
+            return Ok(Some(node.clone()));
+        }
+        let node = self.inner.get_node_option(node_key)?;
+        if let Some(node) = &node {
+            self.nodes.lock().insert(*node_key, node.clone());
+        }
+        Ok(node)
+    }
+}
+
 pub struct StorageTreeReader<K: RawKey> {
     store: Arc<dyn StateNodeStore>,
+    codec: Arc<dyn NodeCodec<K>>,
     cache: StateCache<K>,
 }
 impl<K> TreeReader<K> for StorageTreeReader<K>
 where
     K: RawKey,
 {
+    fn get_node(&self, node_key: &NodeKey) -> Result<Node<K>> {
+        node_or_missing(self.get_node_option(node_key)?, node_key)
+    }
+
     fn get_node_option(&self, node_key: &NodeKey) -> Result<Option<Node<K>>> {
         if node_key == &*SPARSE_MERKLE_PLACEHOLDER_HASH {
             return Ok(Some(Node::new_null()));
@@ -354,7 +1880,7 @@ where
             return Ok(Some(n));
         }
         match self.store.get(node_key) {
-            Ok(Some(n)) => Ok(Some(n.try_into()?)),
+            Ok(Some(n)) => Ok(Some(self.codec.decode(n)?)),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
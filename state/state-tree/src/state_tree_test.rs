@@ -1,14 +1,19 @@
 use super::*;
 use crate::mock::MockStateNodeStore;
+use crate::TypedStateTree;
 use anyhow::Result;
 use forkable_jellyfish_merkle::blob::Blob;
-use forkable_jellyfish_merkle::{HashValueKey, RawKey};
+use forkable_jellyfish_merkle::node_type::{Node, SparseMerkleInternalNode, SparseMerkleLeafNode};
+use forkable_jellyfish_merkle::{HashValueKey, NodeBatch, RawKey};
+use serde::{Deserialize, Serialize};
 use starcoin_config::RocksdbConfig;
 use starcoin_crypto::hash::*;
 use starcoin_storage::db_storage::DBStorage;
 use starcoin_storage::storage::StorageInstance;
 use starcoin_storage::Storage;
+use starcoin_types::state_set::StateSet;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 /// change the `n`th nibble to `nibble`
@@ -216,6 +221,31 @@ pub fn test_state_storage_dump() -> Result<()> {
     Ok(())
 }
 
+#[test]
+pub fn test_state_iter_range() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let hash_value1 = HashValueKey(HashValue::random());
+    let value1 = vec![1u8, 2u8];
+    state.put(hash_value1, value1.clone());
+    let hash_value2 = HashValueKey(HashValue::random());
+    let value2 = vec![3u8, 4u8];
+    state.put(hash_value2, value2.clone());
+    state.commit()?;
+
+    let mut expected = vec![
+        (hash_value1, Blob::from(value1)),
+        (hash_value2, Blob::from(value2)),
+    ];
+    expected.sort_by_key(|(k, _)| k.key_hash());
+    assert_eq!(state.iter_range(None, 10)?, expected);
+    assert_eq!(state.iter_range(None, 1)?, expected[..1].to_vec());
+    assert_eq!(
+        state.iter_range(Some(expected[1].0), 10)?,
+        expected[1..].to_vec()
+    );
+    Ok(())
+}
+
 #[test]
 pub fn test_state_multi_commit_missing_node() -> Result<()> {
     let tmpdir = starcoin_config::temp_dir();
@@ -242,6 +272,10 @@ pub fn test_state_multi_commit_missing_node() -> Result<()> {
     let state1 = StateTree::new(Arc::new(storage.clone()), Some(root_hash1));
     let result = state1.get(&hash_value1);
     assert!(result.is_err(), "Missing node at HashValue");
+    assert!(matches!(
+        result.unwrap_err().downcast_ref::<StateTreeError>(),
+        Some(StateTreeError::MissingNode { .. })
+    ));
 
     let state2 = StateTree::new(Arc::new(storage), Some(root_hash2));
     assert_eq!(state2.get(&hash_value1)?, Some(value12));
@@ -281,3 +315,1249 @@ pub fn test_state_multi_commit_and_flush() -> Result<()> {
     assert_eq!(state2.get(&hash_value2)?, Some(value2));
     Ok(())
 }
+
+#[test]
+pub fn test_verify_proofs_multi() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key = HashValueKey(HashValue::random());
+    let value1 = vec![1u8, 2u8];
+    let value2 = vec![3u8, 4u8];
+    state.put(key, value1.clone());
+    let root1 = state.commit()?;
+    let (_, proof1) = state.get_with_proof(&key)?;
+    state.put(key, value2.clone());
+    let root2 = state.commit()?;
+    let (_, proof2) = state.get_with_proof(&key)?;
+
+    let results = verify_proofs_multi(&[
+        (root1, key, Some(value1.clone().into()), proof1.clone()),
+        (root2, key, Some(value2.clone().into()), proof2.clone()),
+    ]);
+    assert!(results.iter().all(|result| result.is_ok()));
+
+    let results = verify_proofs_multi(&[
+        (root2, key, Some(value1.into()), proof1),
+        (root1, key, Some(value2.into()), proof2),
+    ]);
+    assert!(results.iter().all(|result| result.is_err()));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_compact() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    state.enable_pruning();
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let account3 = update_nibble(&hash_value, 0, 3);
+    state.put(account1, vec![1]);
+    state.put(account2, vec![2]);
+    state.commit()?;
+    state.flush()?;
+    state.put(account1, vec![11]);
+    state.put(account3, vec![3]);
+    state.commit()?;
+    state.flush()?;
+    state.remove(&account2);
+    let root_hash = state.commit()?;
+    let (_, updates) = state.change_sets();
+    assert!(!updates.stale_node_index_batch.is_empty());
+    state.flush()?;
+    let nodes_before = storage.all_nodes().len();
+
+    let compacted_root = state.compact()?;
+    assert_eq!(compacted_root, root_hash);
+    assert!(storage.all_nodes().len() < nodes_before);
+
+    // only the live nodes are left in storage
+    let live_storage = Arc::new(MockStateNodeStore::new());
+    let live = StateTree::new(live_storage.clone(), None);
+    live.put(account1, vec![11]);
+    live.put(account3, vec![3]);
+    assert_eq!(live.commit()?, root_hash);
+    live.flush()?;
+    assert_eq!(storage.all_nodes().len(), live_storage.all_nodes().len());
+    assert_eq!(state.root_hash(), root_hash);
+    let (_, updates) = state.change_sets();
+    assert_eq!(updates.stale_node_index_batch.len(), 0);
+    assert_eq!(state.get(&account1)?, Some(vec![11]));
+    assert_eq!(state.get(&account2)?, None);
+    assert_eq!(state.get(&account3)?, Some(vec![3]));
+
+    let reopened = StateTree::new(storage, Some(compacted_root));
+    assert_eq!(reopened.get(&account1)?, Some(vec![11]));
+    assert_eq!(reopened.get(&account3)?, Some(vec![3]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_proof_cache() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    state.enable_proof_cache(16);
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8, 2u8]);
+    let root_hash = state.commit()?;
+
+    let (value, proof) = state.get_with_proof(&key)?;
+    assert_eq!(state.proof_cache_hits(), 0);
+    let (cached_value, cached_proof) = state.get_with_proof(&key)?;
+    assert_eq!(state.proof_cache_hits(), 1);
+    assert_eq!(value, cached_value);
+    assert_eq!(proof, cached_proof);
+    cached_proof.verify(root_hash, key.key_hash(), Some(&vec![1u8, 2u8].into()))?;
+
+    state.put(key, vec![3u8, 4u8]);
+    let new_root_hash = state.commit()?;
+    let (value, proof) = state.get_with_proof(&key)?;
+    assert_eq!(state.proof_cache_hits(), 1);
+    assert_eq!(value, Some(vec![3u8, 4u8]));
+    proof.verify(new_root_hash, key.key_hash(), Some(&vec![3u8, 4u8].into()))?;
+    Ok(())
+}
+
+#[test]
+pub fn test_state_sibling_path() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let account3 = update_nibble(&account2, 1, 3);
+    state.put(account1, vec![1]);
+    state.put(account2, vec![2]);
+    state.put(account3, vec![3]);
+    let root_hash = state.commit()?;
+
+    let key_hash = account3.key_hash();
+    let siblings = state.sibling_path(&account3)?;
+    assert!(!siblings.is_empty());
+    let blob: Blob = vec![3u8].into();
+    let leaf_hash = SparseMerkleLeafNode::new(key_hash, blob.crypto_hash()).crypto_hash();
+    let computed_root =
+        siblings
+            .iter()
+            .zip(key_hash.iter_bits())
+            .rev()
+            .fold(leaf_hash, |hash, (sibling, bit)| {
+                if bit {
+                    SparseMerkleInternalNode::new(*sibling, hash).crypto_hash()
+                } else {
+                    SparseMerkleInternalNode::new(hash, *sibling).crypto_hash()
+                }
+            });
+    assert_eq!(computed_root, root_hash);
+    assert_eq!(computed_root, state.root_hash());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_put_blob() -> Result<()> {
+    let state1 = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let state2 = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+
+    state1.put(key1, vec![1u8, 2u8]);
+    state1.put(key2, vec![3u8]);
+    state2.put_blob(key1, Blob::from(vec![1u8, 2u8]))?;
+    state2.put_blob(key2, Blob::from(vec![3u8]))?;
+    assert_eq!(state1.commit()?, state2.commit()?);
+    assert_eq!(state2.get(&key1)?, Some(vec![1u8, 2u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_apply_state_set() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    state.put(key1, vec![1u8]);
+    let root1 = state.commit()?;
+
+    let state_set = StateSet::new(vec![
+        (key1.encode_key()?, vec![11u8]),
+        (key2.encode_key()?, vec![2u8]),
+    ]);
+    let root2 = state.apply_state_set(state_set)?;
+    assert_ne!(root1, root2);
+    assert_eq!(state.root_hash(), root2);
+    assert!(!state.is_dirty());
+    assert_eq!(state.get(&key1)?, Some(vec![11u8]));
+    assert_eq!(state.get(&key2)?, Some(vec![2u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_commit_with_undo() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    state.put(key1, vec![1u8]);
+    let root1 = state.commit()?;
+
+    state.put(key1, vec![11u8]);
+    state.put(key2, vec![2u8]);
+    let (root2, undo) = state.commit_with_undo()?;
+    assert_ne!(root1, root2);
+    assert_eq!(undo.prev_root_hash(), root1);
+    assert_eq!(undo.root_hash(), root2);
+    assert_eq!(undo.prev_values().len(), 2);
+    assert_eq!(state.get(&key1)?, Some(vec![11u8]));
+
+    let restored_root = state.apply_undo(undo)?;
+    assert_eq!(restored_root, root1);
+    assert_eq!(state.root_hash(), root1);
+    assert_eq!(state.get(&key1)?, Some(vec![1u8]));
+    assert_eq!(state.get(&key2)?, None);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_get_range_with_proof() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    for i in 0..5u8 {
+        state.put(HashValueKey(HashValue::random()), vec![i]);
+    }
+    let root_hash = state.commit()?;
+
+    let (page1, proof1) = state.get_range_with_proof(None, 2)?;
+    assert_eq!(page1.len(), 2);
+    let mut leaves: Vec<(HashValue, HashValue)> = page1
+        .iter()
+        .map(|(k, v)| (k.key_hash(), v.crypto_hash()))
+        .collect();
+    proof1.verify(root_hash, leaves.as_slice())?;
+    assert!(proof1
+        .verify(HashValue::random(), leaves.as_slice())
+        .is_err());
+
+    let (page2, proof2) = state.get_range_with_proof(Some(&page1[1].0), 2)?;
+    assert_eq!(page2.len(), 2);
+    assert!(page2[0].0.key_hash() > page1[1].0.key_hash());
+    leaves.extend(page2.iter().map(|(k, v)| (k.key_hash(), v.crypto_hash())));
+    proof2.verify(root_hash, leaves.as_slice())?;
+    // a page is not complete if a leaf is missing
+    leaves.remove(1);
+    assert!(proof2.verify(root_hash, leaves.as_slice()).is_err());
+
+    let (page3, proof3) = state.get_range_with_proof(Some(&page2[1].0), 2)?;
+    assert_eq!(page3.len(), 1);
+    let leaves: Vec<(HashValue, HashValue)> = page1
+        .iter()
+        .chain(page2.iter())
+        .chain(page3.iter())
+        .map(|(k, v)| (k.key_hash(), v.crypto_hash()))
+        .collect();
+    proof3.verify(root_hash, leaves.as_slice())?;
+    Ok(())
+}
+
+#[test]
+pub fn test_state_open_missing_root() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    let unflushed_root = state.commit()?;
+    state.put(key, vec![2u8]);
+    state.commit()?;
+    state.flush()?;
+    let flushed_root = state.root_hash();
+
+    let lazy = StateTree::<HashValueKey>::new_lazy(storage.clone(), Some(unflushed_root));
+    assert!(lazy.get(&key).is_err());
+    let lazy = StateTree::<HashValueKey>::open(storage.clone(), Some(unflushed_root), false)?;
+    assert!(lazy.get(&key).is_err());
+    assert!(StateTree::<HashValueKey>::open(storage.clone(), Some(unflushed_root), true).is_err());
+
+    let eager = StateTree::<HashValueKey>::open(storage.clone(), Some(flushed_root), true)?;
+    assert_eq!(eager.get(&key)?, Some(vec![2u8]));
+    let empty = StateTree::<HashValueKey>::open(storage, None, true)?;
+    assert_eq!(empty.get(&key)?, None);
+    Ok(())
+}
+
+#[derive(Default)]
+struct IdentityNodeCodec {
+    encoded: AtomicUsize,
+    decoded: AtomicUsize,
+}
+
+impl NodeCodec<HashValueKey> for IdentityNodeCodec {
+    fn encode(&self, node: &Node<HashValueKey>) -> Result<StateNode> {
+        self.encoded.fetch_add(1, Ordering::Relaxed);
+        DefaultNodeCodec.encode(node)
+    }
+
+    fn decode(&self, node: StateNode) -> Result<Node<HashValueKey>> {
+        self.decoded.fetch_add(1, Ordering::Relaxed);
+        DefaultNodeCodec.decode(node)
+    }
+}
+
+#[test]
+pub fn test_state_node_codec() -> Result<()> {
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    let default_state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    default_state.put(key1, vec![1u8]);
+    default_state.put(key2, vec![2u8]);
+    let default_root = default_state.commit()?;
+
+    let storage = Arc::new(MockStateNodeStore::new());
+    let codec = Arc::new(IdentityNodeCodec::default());
+    let state = StateTree::<HashValueKey>::with_node_codec(storage.clone(), None, codec.clone());
+    state.put(key1, vec![1u8]);
+    state.put(key2, vec![2u8]);
+    let root = state.commit()?;
+    assert_eq!(root, default_root);
+    state.flush()?;
+    assert!(codec.encoded.load(Ordering::Relaxed) > 0);
+
+    let reopened = StateTree::<HashValueKey>::with_node_codec(storage, Some(root), codec.clone());
+    assert_eq!(reopened.get(&key1)?, Some(vec![1u8]));
+    assert_eq!(reopened.get(&key2)?, Some(vec![2u8]));
+    assert!(codec.decoded.load(Ordering::Relaxed) > 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_get_with_proof_typed() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key = HashValueKey(HashValue::random());
+    let absent_key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8, 2u8]);
+    let root_hash = state.commit()?;
+
+    let (value, proof, kind) = state.get_with_proof_typed(&key)?;
+    assert_eq!(kind, ProofKind::Inclusion);
+    assert_eq!(value, Some(Blob::from(vec![1u8, 2u8])));
+    proof.verify(root_hash, key.key_hash(), value.as_ref())?;
+
+    let (value, proof, kind) = state.get_with_proof_typed(&absent_key)?;
+    assert_eq!(kind, ProofKind::Exclusion);
+    assert_eq!(value, None);
+    proof.verify(root_hash, absent_key.key_hash(), None)?;
+    Ok(())
+}
+
+#[test]
+pub fn test_state_diff_cost_estimate() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let account3 = update_nibble(&hash_value, 0, 3);
+    state.put(account1, vec![1]);
+    state.put(account2, vec![2]);
+    state.put(account3, vec![3]);
+    let root1 = state.commit()?;
+    state.flush()?;
+    assert_eq!(state.diff_cost_estimate(root1)?, 0);
+
+    state.put(account2, vec![22]);
+    let root2 = state.commit()?;
+    assert_ne!(root1, root2);
+    assert_eq!(state.diff_cost_estimate(root1)?, 1);
+    assert_eq!(state.diff_cost_estimate(root2)?, 0);
+    Ok(())
+}
+
+#[cfg(feature = "metered")]
+#[test]
+pub fn test_state_commit_timed() -> Result<()> {
+    let state1 = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let state2 = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let key = HashValueKey(HashValue::random());
+    state1.put(key, vec![1u8]);
+    state2.put(key, vec![1u8]);
+    let (root_hash, duration) = state1.commit_timed()?;
+    assert!(duration >= std::time::Duration::from_secs(0));
+    assert_eq!(root_hash, state2.commit()?);
+    assert_eq!(state1.root_hash(), root_hash);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_prune_keep_last() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    state.enable_pruning();
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let mut roots = vec![];
+    for i in 0..5u8 {
+        state.put(account1, vec![i]);
+        state.put(account2, vec![i, i]);
+        roots.push(state.commit()?);
+        state.flush()?;
+    }
+    let nodes_before = storage.all_nodes().len();
+    let removed = state.prune_keep_last(2)?;
+    assert!(removed > 0);
+    assert_eq!(storage.all_nodes().len(), nodes_before - removed);
+
+    for (i, root) in roots.iter().enumerate().skip(3) {
+        let tree = StateTree::new(storage.clone(), Some(*root));
+        assert_eq!(tree.get(&account1)?, Some(vec![i as u8]));
+        assert_eq!(tree.get(&account2)?, Some(vec![i as u8, i as u8]));
+    }
+    let old_tree = StateTree::new(storage.clone(), Some(roots[2]));
+    assert!(old_tree.get(&account1).is_err());
+
+    // nothing more to prune
+    assert_eq!(state.prune_keep_last(2)?, 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_negative_cache() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    state.enable_negative_cache(16);
+    let key = HashValueKey(HashValue::random());
+    let absent_key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    state.commit()?;
+
+    assert_eq!(state.get(&absent_key)?, None);
+    assert_eq!(state.negative_cache_hits(), 0);
+    assert_eq!(state.get(&absent_key)?, None);
+    assert_eq!(state.negative_cache_hits(), 1);
+    // present keys are not cached
+    assert_eq!(state.get(&key)?, Some(vec![1u8]));
+    assert_eq!(state.get(&key)?, Some(vec![1u8]));
+    assert_eq!(state.negative_cache_hits(), 1);
+
+    state.put(absent_key, vec![2u8]);
+    state.commit()?;
+    assert_eq!(state.get(&absent_key)?, Some(vec![2u8]));
+    assert_eq!(state.negative_cache_hits(), 1);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_negative_cache_root_change() -> Result<()> {
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    let source = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    source.put(key1, vec![1u8]);
+    let root1 = source.commit()?;
+    source.put(key2, vec![2u8]);
+    source.commit()?;
+    let diff = source.state_diff(root1)?;
+
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    state.enable_negative_cache(16);
+    state.put(key1, vec![1u8]);
+    state.commit()?;
+
+    // apply_diff
+    assert_eq!(state.get(&key2)?, None);
+    state.apply_diff(&diff)?;
+    assert_eq!(state.get(&key2)?, Some(vec![2u8]));
+
+    // apply_undo
+    state.remove(&key2);
+    let (_, undo) = state.commit_with_undo()?;
+    assert_eq!(state.get(&key2)?, None);
+    state.apply_undo(undo)?;
+    assert_eq!(state.get(&key2)?, Some(vec![2u8]));
+
+    // rollback_to
+    let snapshot = state.snapshot();
+    state.remove(&key2);
+    state.commit()?;
+    assert_eq!(state.get(&key2)?, None);
+    state.rollback_to(snapshot)?;
+    assert_eq!(state.get(&key2)?, Some(vec![2u8]));
+
+    // apply_change_set
+    let replica = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    replica.enable_negative_cache(16);
+    assert_eq!(replica.get(&key1)?, None);
+    let writer = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    writer.put(key1, vec![1u8]);
+    let (root, manifest) = writer.commit_with_manifest()?;
+    replica.apply_change_set(root, manifest)?;
+    assert_eq!(replica.get(&key1)?, Some(vec![1u8]));
+    assert_eq!(state.negative_cache_hits(), 0);
+    assert_eq!(replica.negative_cache_hits(), 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_commit_with_manifest() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let replica_storage = Arc::new(MockStateNodeStore::new());
+    let replica = StateTree::new(replica_storage.clone(), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+
+    state.put(key1, vec![1u8]);
+    let (root1, manifest1) = state.commit_with_manifest()?;
+    assert_eq!(replica.num_leaves()?, 0);
+    replica.apply_change_set(root1, manifest1)?;
+    assert_eq!(replica.root_hash(), root1);
+    assert_eq!(replica.get(&key1)?, Some(vec![1u8]));
+    assert_eq!(replica.num_leaves()?, 1);
+
+    state.put(key1, vec![11u8]);
+    state.put(key2, vec![2u8]);
+    let (root2, manifest2) = state.commit_with_manifest()?;
+    let mut corrupted = manifest2.clone();
+    corrupted[0].0 = HashValue::random();
+    assert!(replica.apply_change_set(root2, corrupted).is_err());
+    replica.apply_change_set(root2, manifest2)?;
+    assert_eq!(replica.root_hash(), root2);
+    assert_eq!(replica.num_leaves()?, 2);
+
+    let reopened = StateTree::new(replica_storage, Some(root2));
+    assert_eq!(reopened.get(&key1)?, Some(vec![11u8]));
+    assert_eq!(reopened.get(&key2)?, Some(vec![2u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_changed_keys_between() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let account3 = update_nibble(&hash_value, 0, 3);
+    let account4 = update_nibble(&account3, 1, 4);
+    state.put(account1, vec![1]);
+    state.put(account2, vec![2]);
+    state.put(account3, vec![3]);
+    let root1 = state.commit()?;
+    state.flush()?;
+    state.put(account2, vec![22]);
+    let root2 = state.commit()?;
+    state.flush()?;
+    state.put(account4, vec![4]);
+    state.remove(&account1);
+    let root3 = state.commit()?;
+    state.flush()?;
+
+    assert!(state.changed_keys_between(root1, root1)?.is_empty());
+    assert_eq!(state.changed_keys_between(root1, root2)?, vec![account2]);
+    let mut changed = state.changed_keys_between(root1, root3)?;
+    changed.sort_by_key(|key| key.key_hash());
+    let mut expected = vec![account1, account2, account4];
+    expected.sort_by_key(|key| key.key_hash());
+    assert_eq!(changed, expected);
+    assert_eq!(
+        state.changed_keys_between(root3, root1)?.len(),
+        expected.len()
+    );
+    Ok(())
+}
+
+#[test]
+pub fn test_state_commit_not_in() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    state.put(key1, vec![1u8]);
+    let original_root = state.commit()?;
+
+    state.put(key2, vec![2u8]);
+    let root = state.commit_not_in(&[original_root])?;
+    assert_ne!(root, original_root);
+
+    // stage the inverse change
+    state.remove(&key2);
+    assert!(state.commit_not_in(&[original_root]).is_err());
+    assert_eq!(state.root_hash(), root);
+    assert!(state.is_dirty());
+    assert_eq!(state.commit()?, original_root);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_cache_stats() -> Result<()> {
+    let s = MockStateNodeStore::new();
+    let state = StateTree::new(Arc::new(s), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    state.put(key1, vec![1u8]);
+    state.commit()?;
+    state.flush()?;
+
+    // the cache is reset after flush, so key1 is read from storage.
+    state.reset_cache_stats();
+    assert_eq!(state.cache_stats(), CacheStats::default());
+    assert_eq!(state.get(&key1)?, Some(vec![1u8]));
+    let stats = state.cache_stats();
+    assert_eq!(stats.hits, 0);
+    assert!(stats.misses > 0);
+
+    // key2 is only committed, so it's read from the cache.
+    state.put(key2, vec![2u8]);
+    state.commit()?;
+    state.reset_cache_stats();
+    assert_eq!(state.get(&key2)?, Some(vec![2u8]));
+    let stats = state.cache_stats();
+    assert!(stats.hits > 0);
+    assert_eq!(stats.misses, 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_new_in_memory() -> Result<()> {
+    let state = StateTree::<HashValueKey>::new_in_memory();
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    let root_hash = state.commit()?;
+    state.flush()?;
+    assert_eq!(state.get(&key)?, Some(vec![1u8]));
+    assert_eq!(state.root_hash(), root_hash);
+
+    let fresh = StateTree::<HashValueKey>::new_in_memory();
+    assert_eq!(fresh.root_hash(), *SPARSE_MERKLE_PLACEHOLDER_HASH);
+    assert_eq!(fresh.get(&key)?, None);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_verify_reachable_multi() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let replica_storage = Arc::new(MockStateNodeStore::new());
+    let replica = StateTree::new(replica_storage.clone(), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+
+    state.put(key1, vec![1u8]);
+    let (root1, manifest1) = state.commit_with_manifest()?;
+    replica.apply_change_set(root1, manifest1)?;
+    state.put(key2, vec![2u8]);
+    let (root2, manifest2) = state.commit_with_manifest()?;
+    replica.apply_change_set(root2, manifest2)?;
+
+    let results = replica.verify_reachable_multi(&[root1, root2])?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, root1);
+    assert_eq!(results[1].0, root2);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    let missing_root = HashValue::random();
+    let root1_node = replica_storage.get(&root1)?.unwrap();
+    replica_storage.put(root2, root1_node)?;
+    let results = replica.verify_reachable_multi(&[root1, root2, missing_root])?;
+    assert!(results[0].1.is_ok());
+    assert!(results[1].1.is_err());
+    assert!(results[2].1.is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_commit_with_stale() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    state.put(key1, vec![1u8]);
+    state.put(key2, vec![2u8]);
+    let (root1, stale) = state.commit_with_stale()?;
+    assert!(stale.is_empty());
+    state.flush()?;
+
+    let blob: Blob = vec![1u8].into();
+    let old_leaf_hash =
+        SparseMerkleLeafNode::new(key1.key_hash(), blob.crypto_hash()).crypto_hash();
+    state.put(key1, vec![11u8]);
+    let (root2, stale) = state.commit_with_stale()?;
+    assert_ne!(root1, root2);
+    assert!(!stale.is_empty());
+    assert!(stale.contains(&old_leaf_hash));
+    assert!(stale.contains(&root1));
+
+    assert_eq!(state.commit_with_stale()?, (root2, vec![]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_proof_server() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let keys: Vec<HashValueKey> = (0..8).map(|_| HashValueKey(HashValue::random())).collect();
+    for (i, key) in keys.iter().enumerate() {
+        state.put(*key, vec![i as u8]);
+    }
+    let root_hash = state.commit()?;
+    state.flush()?;
+
+    let server = state.proof_server();
+    assert_eq!(server.root_hash(), root_hash);
+    for (i, key) in keys.iter().enumerate() {
+        let (value, proof) = server.proof(key)?;
+        assert_eq!(value, Some(vec![i as u8]));
+        proof.verify(root_hash, key.key_hash(), Some(&vec![i as u8].into()))?;
+    }
+    let absent = HashValueKey(HashValue::random());
+    let (value, proof) = server.proof(&absent)?;
+    assert!(value.is_none());
+    proof.verify(root_hash, absent.key_hash(), None)?;
+    assert!(!server.is_stale());
+
+    state.put(keys[0], vec![100u8]);
+    state.commit()?;
+    assert!(server.is_stale());
+    assert!(server.proof(&keys[0]).is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_open_latest() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let empty = StateTree::<HashValueKey>::open_latest(storage.clone())?;
+    assert_eq!(empty.root_hash(), *SPARSE_MERKLE_PLACEHOLDER_HASH);
+
+    let state = StateTree::new(storage.clone(), None);
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    let root1 = state.commit()?;
+    state.flush_and_record_root()?;
+    state.put(key, vec![2u8]);
+    let root2 = state.commit()?;
+    state.flush()?;
+    assert_eq!(storage.get_latest_root()?, Some(root1));
+    state.flush_and_record_root()?;
+
+    let reopened = StateTree::<HashValueKey>::open_latest(storage)?;
+    assert_eq!(reopened.root_hash(), root2);
+    assert_eq!(reopened.get(&key)?, Some(vec![2u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_diff_round_trip() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let replica = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let key1 = HashValueKey(HashValue::random());
+    let key2 = HashValueKey(HashValue::random());
+    let key3 = HashValueKey(HashValue::random());
+    for tree in [&state, &replica] {
+        tree.put(key1, vec![1u8]);
+        tree.put(key2, vec![2u8]);
+        tree.commit()?;
+    }
+    let from_root = state.root_hash();
+    assert_eq!(replica.root_hash(), from_root);
+
+    state.put(key1, vec![11u8]);
+    state.remove(&key2);
+    state.put(key3, vec![3u8]);
+    let to_root = state.commit()?;
+    let diff = state.state_diff(from_root)?;
+    assert_eq!(diff.from_root(), from_root);
+    assert_eq!(diff.to_root(), to_root);
+    assert_eq!(diff.changes().len(), 3);
+
+    let bytes = bcs_ext::to_bytes(&diff)?;
+    let decoded: StateDiff<HashValueKey> = bcs_ext::from_bytes(&bytes)?;
+    assert_eq!(decoded, diff);
+    assert_eq!(replica.apply_diff(&decoded)?, to_root);
+    assert_eq!(replica.get(&key1)?, Some(vec![11u8]));
+    assert_eq!(replica.get(&key2)?, None);
+    assert_eq!(replica.get(&key3)?, Some(vec![3u8]));
+
+    assert!(replica.apply_diff(&decoded).is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_auto_flush() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new_auto_flush(storage.clone(), None, 10);
+    let keys: Vec<HashValueKey> = (0..50).map(|_| HashValueKey(HashValue::random())).collect();
+    let mut auto_flushes = 0;
+    for (i, key) in keys.iter().enumerate() {
+        if state.put_with_auto_flush(*key, vec![i as u8])? {
+            auto_flushes += 1;
+            assert!(!state.is_dirty());
+        }
+    }
+    assert!(auto_flushes > 0);
+    let root_hash = state.commit()?;
+    state.flush()?;
+
+    let reopened = StateTree::<HashValueKey>::new(storage, Some(root_hash));
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(reopened.get(key)?, Some(vec![i as u8]));
+    }
+
+    let plain = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    for key in keys.iter() {
+        assert!(!plain.put_with_auto_flush(*key, vec![0u8])?);
+    }
+
+    // plain put never flushes, even on an auto flush tree
+    let put_storage = Arc::new(MockStateNodeStore::new());
+    let put_only = StateTree::new_auto_flush(put_storage.clone(), None, 10);
+    for key in keys.iter() {
+        put_only.put(*key, vec![0u8]);
+    }
+    assert!(put_only.is_dirty());
+    assert!(put_storage.all_nodes().is_empty());
+
+    // the limit is kept by clone
+    let cloned = state.clone();
+    let mut cloned_auto_flushes = 0;
+    for key in keys.iter() {
+        if cloned.put_with_auto_flush(*key, vec![0u8])? {
+            cloned_auto_flushes += 1;
+        }
+    }
+    assert!(cloned_auto_flushes > 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_missing_node_policy() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    let unflushed_root = state.commit()?;
+
+    let reader = StateTree::<HashValueKey>::new(storage, Some(unflushed_root));
+    assert!(reader.get(&key).is_err());
+    reader.set_missing_node_policy(MissingNodePolicy::TreatAsAbsent);
+    assert_eq!(reader.get(&key)?, None);
+    reader.set_missing_node_policy(MissingNodePolicy::Error);
+    assert!(reader.get(&key).is_err());
+
+    state.flush()?;
+    reader.set_missing_node_policy(MissingNodePolicy::TreatAsAbsent);
+    assert_eq!(reader.get(&key)?, Some(vec![1u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_put_all() -> Result<()> {
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&account1, 2, 3);
+    let account3 = update_nibble(&account1, 0, 2);
+    let items = vec![
+        (account1, vec![1, 1, 0]),
+        (account2, vec![0, 0, 0]),
+        (account3, vec![2, 2, 2]),
+    ];
+
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    for (k, v) in items.clone() {
+        state.put(k, v);
+    }
+    let expect_root = state.commit()?;
+
+    let batched = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    batched.put_all(items);
+    assert_eq!(batched.get(&account2)?, Some(vec![0, 0, 0]));
+    assert_eq!(batched.root_hash(), *SPARSE_MERKLE_PLACEHOLDER_HASH);
+    assert_eq!(batched.commit()?, expect_root);
+    assert_eq!(batched.get(&account3)?, Some(vec![2, 2, 2]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_get_many_with_proof() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account1 = update_nibble(&account1, 1, 1);
+    let account2 = update_nibble(&account1, 1, 2);
+    let account3 = update_nibble(&account1, 1, 3);
+    let absent = update_nibble(&account1, 1, 4);
+    state.put_all(vec![
+        (account1, vec![0, 0, 0]),
+        (account2, vec![1, 1, 1]),
+        (account3, vec![2, 2, 2]),
+    ]);
+    let root = state.commit()?;
+
+    let keys = vec![account1, account2, account3, absent];
+    let results = state.get_many_with_proof(&keys)?;
+    assert_eq!(results.len(), keys.len());
+    for (key, (value, proof)) in keys.iter().zip(results.into_iter()) {
+        let (expected, _) = state.get_with_proof(key)?;
+        assert_eq!(value, expected);
+        let blob = value.map(Blob::from);
+        proof.verify(root, key.key_hash(), blob.as_ref())?;
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_state_diff_with_other_root() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&account1, 0, 2);
+    let account3 = update_nibble(&account1, 2, 3);
+    state.put_all(vec![
+        (account1, vec![1u8]),
+        (account2, vec![2u8]),
+        (account3, vec![3u8]),
+    ]);
+    let old_root = state.commit()?;
+    state.flush()?;
+    assert!(state.diff(old_root)?.is_empty());
+
+    state.put(account3, vec![4u8]);
+    state.commit()?;
+    let changes = state.diff(old_root)?;
+    assert_eq!(
+        changes,
+        vec![(
+            account3,
+            Some(Blob::from(vec![4u8])),
+            Some(Blob::from(vec![3u8]))
+        )]
+    );
+    Ok(())
+}
+
+#[test]
+pub fn test_state_pruning_not_enabled() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let key = HashValueKey(HashValue::random());
+    state.put(key, vec![1u8]);
+    let root1 = state.commit()?;
+    state.flush()?;
+    state.put(key, vec![2u8]);
+    state.commit()?;
+    state.flush()?;
+    assert!(state.prune_keep_last(1).is_err());
+    assert!(state.prune(root1).is_err());
+    assert!(state.compact().is_err());
+    assert_eq!(state.get(&key)?, Some(vec![2u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_prune_shared_store() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let tree1 = StateTree::new(storage.clone(), None);
+    let tree2 = StateTree::new(storage.clone(), None);
+    let key = HashValueKey(HashValue::random());
+    tree1.put(key, vec![1u8]);
+    let root1 = tree1.commit()?;
+    tree1.flush()?;
+    // the same leaf is the root of both trees, stored once.
+    tree2.put(key, vec![1u8]);
+    assert_eq!(tree2.commit()?, root1);
+    tree2.flush()?;
+    tree1.put(key, vec![2u8]);
+    tree1.commit()?;
+    tree1.flush()?;
+
+    // pruning is not enabled on trees sharing a store, the shared node is kept.
+    assert!(tree1.prune_keep_last(1).is_err());
+    assert!(tree1.prune(root1).is_err());
+    assert!(tree1.compact().is_err());
+    assert!(storage.get(&root1)?.is_some());
+    let reopened = StateTree::new(storage, Some(root1));
+    assert_eq!(reopened.get(&key)?, Some(vec![1u8]));
+    assert_eq!(tree2.get(&key)?, Some(vec![1u8]));
+    Ok(())
+}
+
+#[test]
+pub fn test_state_prune_before_root() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    state.enable_pruning();
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let mut roots = vec![];
+    for i in 0..4u8 {
+        state.put_all(vec![(account1, vec![i]), (account2, vec![i, i])]);
+        roots.push(state.commit()?);
+        state.flush()?;
+    }
+    assert!(state.prune(HashValue::random()).is_err());
+    let nodes_before = storage.all_nodes().len();
+    let removed = state.prune(roots[2])?;
+    assert!(removed > 0);
+    assert_eq!(storage.all_nodes().len(), nodes_before - removed);
+
+    let old_tree = StateTree::new(storage.clone(), Some(roots[1]));
+    assert!(old_tree.get(&account1).is_err());
+    for (i, root) in roots.iter().enumerate().skip(2) {
+        let tree = StateTree::new(storage.clone(), Some(*root));
+        assert_eq!(tree.get(&account1)?, Some(vec![i as u8]));
+    }
+    assert_eq!(state.get(&account2)?, Some(vec![3u8, 3u8]));
+    assert_eq!(state.prune(roots[2])?, 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_pending_root_hash() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    assert_eq!(state.pending_root_hash()?, *SPARSE_MERKLE_PLACEHOLDER_HASH);
+    let account1 = HashValueKey(HashValue::random());
+    let account2 = HashValueKey(HashValue::random());
+    state.put(account1, vec![1u8]);
+    let committed_root = state.commit()?;
+
+    state.put(account1, vec![2u8]);
+    state.put(account2, vec![3u8]);
+    let pending_root = state.pending_root_hash()?;
+    assert_ne!(pending_root, committed_root);
+    assert_eq!(state.root_hash(), committed_root);
+    assert!(state.is_dirty());
+    assert_eq!(state.commit()?, pending_root);
+    assert_eq!(state.pending_root_hash()?, pending_root);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_snapshot_rollback() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let account1 = HashValueKey(HashValue::random());
+    let account2 = HashValueKey(HashValue::random());
+    let account3 = HashValueKey(HashValue::random());
+    state.put(account1, vec![1u8]);
+    let root = state.commit()?;
+
+    let snapshot = state.snapshot();
+    assert_eq!(snapshot.root_hash(), root);
+    state.put(account2, vec![2u8]);
+    state.commit()?;
+    state.put(account3, vec![3u8]);
+    state.rollback_to(snapshot.clone())?;
+    assert_eq!(state.root_hash(), root);
+    assert!(!state.is_dirty());
+    assert_eq!(state.get(&account1)?, Some(vec![1u8]));
+    assert_eq!(state.get(&account2)?, None);
+    assert_eq!(state.get(&account3)?, None);
+
+    state.flush()?;
+    assert!(state.rollback_to(snapshot).is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_contains_key() -> Result<()> {
+    let state = StateTree::<HashValueKey>::new(Arc::new(MockStateNodeStore::new()), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account1 = update_nibble(&account1, 2, 2);
+    assert!(!state.contains_key(&account1)?);
+    state.put(account1, vec![0, 0, 0]);
+    assert!(state.contains_key(&account1)?);
+    assert!(!state.contains_key(&update_nibble(&hash_value, 0, 8))?);
+
+    state.commit()?;
+    assert!(state.contains_key(&account1)?);
+    assert!(!state.contains_key(&update_nibble(&hash_value, 0, 8))?);
+
+    let account2 = update_nibble(&account1, 0, 2);
+    let account3 = update_nibble(&account1, 2, 3);
+    state.put(account2, vec![0, 0, 0]);
+    state.commit()?;
+    assert!(state.contains_key(&account2)?);
+    assert!(!state.contains_key(&account3)?);
+
+    state.remove(&account1);
+    assert!(!state.contains_key(&account1)?);
+    state.commit()?;
+    assert!(!state.contains_key(&account1)?);
+    assert!(state.contains_key(&account2)?);
+    Ok(())
+}
+
+#[test]
+pub fn test_verify_proofs() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    let absent = update_nibble(&hash_value, 0, 3);
+    state.put_all(vec![(account1, vec![1u8]), (account2, vec![2u8])]);
+    let root = state.commit()?;
+
+    let mut entries = vec![];
+    for key in [account1, account2, absent] {
+        let (value, proof) = state.get_with_proof(&key)?;
+        entries.push((key, value.map(Blob::from), proof));
+    }
+    assert!(entries[2].1.is_none());
+    verify_proofs(root, &entries)?;
+    verify_proofs(root, &[])?;
+
+    entries[1].1 = Some(vec![3u8].into());
+    let err = verify_proofs(root, &entries).unwrap_err();
+    assert!(err.to_string().contains("index 1"));
+    assert!(verify_proofs(HashValue::random(), &entries[..1]).is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_typed_state_tree() -> Result<()> {
+    #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+    struct Balance {
+        token: String,
+        amount: u128,
+    }
+
+    let typed = TypedStateTree::<HashValueKey, Balance>::new(StateTree::new(
+        Arc::new(MockStateNodeStore::new()),
+        None,
+    ));
+    let key = HashValueKey(HashValue::random());
+    let balance = Balance {
+        token: "STC".to_string(),
+        amount: 100,
+    };
+    assert_eq!(typed.get(&key)?, None);
+    typed.put(key, &balance)?;
+    assert_eq!(typed.get(&key)?, Some(balance.clone()));
+    let root = typed.commit()?;
+    assert_eq!(typed.get(&key)?, Some(balance.clone()));
+
+    let plain = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    plain.put(key, bcs_ext::to_bytes(&balance)?);
+    assert_eq!(plain.commit()?, root);
+
+    typed.remove(&key);
+    typed.commit()?;
+    assert_eq!(typed.get(&key)?, None);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_num_leaves() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage.clone(), None);
+    assert_eq!(state.num_leaves()?, 0);
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&account1, 2, 2);
+    let account3 = update_nibble(&hash_value, 0, 3);
+    state.put_all(vec![(account1, vec![1u8]), (account2, vec![2u8])]);
+    state.commit()?;
+    assert_eq!(state.num_leaves()?, 2);
+    state.put_all(vec![(account1, vec![3u8]), (account3, vec![4u8])]);
+    assert_eq!(state.num_leaves()?, 2);
+    state.commit()?;
+    assert_eq!(state.num_leaves()?, 3);
+    state.remove(&account2);
+    let root = state.commit()?;
+    assert_eq!(state.num_leaves()?, 2);
+    assert_eq!(state.num_leaves()?, state.dump()?.len() as u64);
+
+    state.flush()?;
+    let reopened = StateTree::<HashValueKey>::new(storage, Some(root));
+    assert_eq!(reopened.num_leaves()?, 2);
+    reopened.remove(&account1);
+    reopened.commit()?;
+    assert_eq!(reopened.num_leaves()?, 1);
+    assert_eq!(reopened.num_leaves()?, reopened.dump()?.len() as u64);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_commit_to_batch() -> Result<()> {
+    let state = StateTree::new(Arc::new(MockStateNodeStore::new()), None);
+    let account1 = HashValueKey(HashValue::random());
+    let account2 = HashValueKey(HashValue::random());
+    state.put(account1, vec![1u8]);
+    state.commit()?;
+    state.put(account2, vec![2u8]);
+    let (root, batch) = state.commit_to_batch()?;
+    assert_eq!(state.root_hash(), root);
+    assert!(!state.is_dirty());
+    assert!(!batch.is_empty());
+
+    let fresh_store = Arc::new(MockStateNodeStore::new());
+    let writer = StateTree::<HashValueKey>::new(fresh_store.clone(), None);
+    writer.apply_batch(root, batch)?;
+    assert_eq!(fresh_store.get_latest_root()?, None);
+    writer.record_latest_root(root)?;
+    assert_eq!(fresh_store.get_latest_root()?, Some(root));
+    let replayed = StateTree::new(fresh_store.clone(), Some(root));
+    assert_eq!(replayed.get(&account1)?, Some(vec![1u8]));
+    assert_eq!(replayed.get(&account2)?, Some(vec![2u8]));
+
+    assert!(writer
+        .apply_batch(HashValue::random(), NodeBatch::new())
+        .is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_state_concurrent_get() -> Result<()> {
+    let state = Arc::new(StateTree::new(Arc::new(MockStateNodeStore::new()), None));
+    let kvs = (0..100u8)
+        .map(|i| (HashValueKey(HashValue::random()), vec![i]))
+        .collect::<Vec<_>>();
+    state.put_all(kvs.clone());
+    let root = state.commit()?;
+    let kvs = Arc::new(kvs);
+
+    let handles = (0..4)
+        .map(|_| {
+            let state = state.clone();
+            let kvs = kvs.clone();
+            std::thread::spawn(move || -> Result<()> {
+                for (k, v) in kvs.iter() {
+                    assert_eq!(state.get(k)?, Some(v.clone()));
+                    assert_eq!(state.root_hash(), root);
+                }
+                Ok(())
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().expect("reader thread should not panic")?;
+    }
+    assert_eq!(state.root_hash(), root);
+    Ok(())
+}
+
+#[test]
+pub fn test_state_get_with_proof_at_root() -> Result<()> {
+    let storage = Arc::new(MockStateNodeStore::new());
+    let state = StateTree::new(storage, None);
+    state.enable_pruning();
+    let hash_value = HashValue::random().into();
+    let account1 = update_nibble(&hash_value, 0, 1);
+    let account2 = update_nibble(&hash_value, 0, 2);
+    state.put(account1, vec![1u8]);
+    let root1 = state.commit()?;
+    state.flush()?;
+    state.put_all(vec![(account1, vec![2u8]), (account2, vec![3u8])]);
+    let root2 = state.commit()?;
+    state.flush()?;
+    assert_eq!(state.root_hash(), root2);
+
+    let (value, proof) = state.get_with_proof_at_root(&account1, root1)?;
+    assert_eq!(value, Some(Blob::from(vec![1u8])));
+    proof.verify(root1, account1.key_hash(), value.as_ref())?;
+    let (value, proof) = state.get_with_proof_at_root(&account2, root1)?;
+    assert!(value.is_none());
+    proof.verify(root1, account2.key_hash(), None)?;
+
+    state.prune(root2)?;
+    let err = state.get_with_proof_at_root(&account1, root1).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<StateTreeError>(),
+        Some(StateTreeError::MissingNode { .. })
+    ));
+    Ok(())
+}
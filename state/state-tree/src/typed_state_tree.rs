@@ -0,0 +1,69 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::StateTree;
+use anyhow::Result;
+use forkable_jellyfish_merkle::RawKey;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use starcoin_crypto::HashValue;
+use std::marker::PhantomData;
+
+/// A `StateTree` storing values of type `V`, the values are encoded with bcs,
+/// so the root hash is the same as putting the encoded bytes into the underline `StateTree`.
+pub struct TypedStateTree<K: RawKey, V> {
+    tree: StateTree<K>,
+    value_type: PhantomData<V>,
+}
+
+impl<K, V> TypedStateTree<K, V>
+where
+    K: RawKey,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn new(tree: StateTree<K>) -> Self {
+        Self {
+            tree,
+            value_type: PhantomData,
+        }
+    }
+
+    /// The underline byte-level state tree.
+    pub fn inner(&self) -> &StateTree<K> {
+        &self.tree
+    }
+
+    pub fn into_inner(self) -> StateTree<K> {
+        self.tree
+    }
+
+    /// Encode `value` and put it into tree, see `StateTree::put`.
+    pub fn put(&self, key: K, value: &V) -> Result<()> {
+        self.tree.put(key, bcs_ext::to_bytes(value)?);
+        Ok(())
+    }
+
+    /// Read and decode the value of `key`, see `StateTree::get`.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.tree
+            .get(key)?
+            .map(|bytes| bcs_ext::from_bytes(bytes.as_slice()))
+            .transpose()
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.tree.remove(key)
+    }
+
+    pub fn root_hash(&self) -> HashValue {
+        self.tree.root_hash()
+    }
+
+    pub fn commit(&self) -> Result<HashValue> {
+        self.tree.commit()
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.tree.flush()
+    }
+}
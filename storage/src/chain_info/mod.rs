@@ -27,6 +27,7 @@ impl ChainInfoStorage {
     const GENESIS_KEY: &'static str = "genesis";
     const STORAGE_VERSION_KEY: &'static str = "storage_version";
     const SNAPSHOT_RANGE_KEY: &'static str = "snapshot_height";
+    const LATEST_STATE_ROOT_KEY: &'static str = "latest_state_root";
 
     pub fn get_startup_info(&self) -> Result<Option<StartupInfo>> {
         self.get(Self::STARTUP_INFO_KEY.as_bytes())
@@ -95,4 +96,19 @@ impl ChainInfoStorage {
             snapshot_range.try_into()?,
         )
     }
+
+    pub fn get_latest_state_root(&self) -> Result<Option<HashValue>> {
+        self.get(Self::LATEST_STATE_ROOT_KEY.as_bytes())
+            .and_then(|bytes| match bytes {
+                Some(bytes) => Ok(Some(HashValue::from_slice(bytes.as_slice())?)),
+                None => Ok(None),
+            })
+    }
+
+    pub fn save_latest_state_root(&self, state_root: HashValue) -> Result<()> {
+        self.put(
+            Self::LATEST_STATE_ROOT_KEY.as_bytes().to_vec(),
+            state_root.to_vec(),
+        )
+    }
 }
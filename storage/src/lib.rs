@@ -313,6 +313,18 @@ impl StateNodeStore for Storage {
         let batch = CodecWriteBatch::new_puts(nodes.into_iter().collect());
         self.state_node_storage.write_batch(batch)
     }
+
+    fn delete_nodes(&self, keys: Vec<HashValue>) -> Result<()> {
+        self.state_node_storage.delete_all(keys)
+    }
+
+    fn get_latest_root(&self) -> Result<Option<HashValue>> {
+        self.chain_info_storage.get_latest_state_root()
+    }
+
+    fn save_latest_root(&self, root_hash: HashValue) -> Result<()> {
+        self.chain_info_storage.save_latest_state_root(root_hash)
+    }
 }
 
 impl Display for Storage {
@@ -427,10 +427,9 @@ where
             }
             //block has bean processed, and it parent is main chain ,so just connect it to main chain.
             (Some(block_info), None) => {
-                let executed_block = self.main.connect(ExecutedBlock {
-                    block: block.clone(),
-                    block_info,
-                })?;
+                let executed_block = self
+                    .main
+                    .connect(ExecutedBlock::new(block.clone(), block_info))?;
                 self.do_new_head(executed_block, 1, vec![block], 0, vec![])?;
                 Ok(ConnectOk::Connect)
             }
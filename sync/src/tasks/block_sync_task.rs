@@ -292,10 +292,8 @@ where
             Some(block_info) => {
                 //If block_info exists, it means that this block was already executed and try connect in the previous sync, but the sync task was interrupted.
                 //So, we just need to update chain and continue
-                self.chain.connect(ExecutedBlock {
-                    block,
-                    block_info: block_info.clone(),
-                })?;
+                self.chain
+                    .connect(ExecutedBlock::new(block, block_info.clone()))?;
                 block_info
             }
             None => {
@@ -7,11 +7,17 @@ use crate::genesis_config::{ChainId, ConsensusStrategy};
 use crate::language_storage::CORE_CODE_ADDRESS;
 use crate::transaction::SignedUserTransaction;
 use crate::U256;
+use anyhow::{bail, ensure, format_err, Result};
 use bcs_ext::Sample;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
 use schemars::{self, JsonSchema};
-use serde::de::Error;
+use serde::de::{Error, SeqAccess, Unexpected, Visitor};
+use serde::ser::{SerializeStruct, SerializeTuple};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 pub use starcoin_accumulator::accumulator_info::AccumulatorInfo;
+use starcoin_accumulator::proof::AccumulatorProof;
+use starcoin_accumulator::{Accumulator, AccumulatorTreeStore, MerkleAccumulator};
 use starcoin_crypto::hash::{ACCUMULATOR_PLACEHOLDER_HASH, SPARSE_MERKLE_PLACEHOLDER_HASH};
 use starcoin_crypto::{
     hash::{CryptoHash, CryptoHasher, PlainCryptoHash},
@@ -19,7 +25,12 @@ use starcoin_crypto::{
 };
 use starcoin_vm_types::account_config::genesis_address;
 use starcoin_vm_types::transaction::authenticator::AuthenticationKey;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt::Formatter;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
 /// Type for block number.
 pub type BlockNumber = u64;
 
@@ -37,11 +48,38 @@ impl BlockHeaderExtra {
     pub fn as_slice(&self) -> &[u8; 4] {
         &self.0
     }
+
+    /// Check no bit in `reserved_mask` is set, the extra bytes are read as a big-endian u32.
+    pub fn verify_reserved_zero(&self, reserved_mask: u32) -> Result<()> {
+        let reserved_bits = u32::from_be_bytes(self.0) & reserved_mask;
+        ensure!(
+            reserved_bits == 0,
+            "Invalid block header extra {}: reserved bits {:#010x} are set.",
+            self,
+            reserved_bits
+        );
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for BlockHeaderExtra {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", hex::encode(self.0))
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for BlockHeaderExtra {
+    type Err = anyhow::Error;
+
+    /// Parse the hex form of `Display`, the `0x` prefix is optional.
+    fn from_str(s: &str) -> Result<Self> {
+        let literal = s.strip_prefix("0x").unwrap_or(s);
+        ensure!(literal.len() == 8, "Invalid block header extra len");
+        let result = hex::decode(literal)?;
+        ensure!(result.len() == 4, "Invalid block header extra len");
+        let mut extra = [0u8; 4];
+        extra.copy_from_slice(&result);
+        Ok(BlockHeaderExtra::new(extra))
     }
 }
 
@@ -52,17 +90,7 @@ impl<'de> Deserialize<'de> for BlockHeaderExtra {
     {
         if deserializer.is_human_readable() {
             let s = <String>::deserialize(deserializer)?;
-            let literal = s.strip_prefix("0x").unwrap_or(&s);
-            if literal.len() != 8 {
-                return Err(D::Error::custom("Invalid block header extra len"));
-            }
-            let result = hex::decode(literal).map_err(D::Error::custom)?;
-            if result.len() != 4 {
-                return Err(D::Error::custom("Invalid block header extra len"));
-            }
-            let mut extra = [0u8; 4];
-            extra.copy_from_slice(&result);
-            Ok(BlockHeaderExtra::new(extra))
+            BlockHeaderExtra::from_str(&s).map_err(D::Error::custom)
         } else {
             #[derive(::serde::Deserialize)]
             #[serde(rename = "BlockHeaderExtra")]
@@ -79,13 +107,15 @@ impl Serialize for BlockHeaderExtra {
         S: Serializer,
     {
         if serializer.is_human_readable() {
-            format!("0x{}", hex::encode(self.0)).serialize(serializer)
+            self.to_string().serialize(serializer)
         } else {
             serializer.serialize_newtype_struct("BlockHeaderExtra", &self.0)
         }
     }
 }
 
+/// The derived `Ord` is lexicographic on the fields, so it compares `id` first and is not a
+/// chain height order. Use `cmp_by_number` to compare chain tips.
 #[derive(
     Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize, JsonSchema,
 )]
@@ -104,6 +134,23 @@ impl BlockIdAndNumber {
     pub fn number(&self) -> BlockNumber {
         self.number
     }
+
+    /// The genesis block with the given id, at number 0.
+    pub fn genesis(genesis_id: HashValue) -> Self {
+        Self::new(genesis_id, 0)
+    }
+
+    /// Compare by `number` first, and by `id` only to break ties between blocks at the same number.
+    pub fn cmp_by_number(&self, other: &Self) -> Ordering {
+        self.number
+            .cmp(&other.number)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// Whether this block may be an ancestor of `other`, judging by number only.
+    pub fn is_ancestor_candidate(&self, other: &Self) -> bool {
+        self.number < other.number
+    }
 }
 
 impl From<BlockHeader> for BlockIdAndNumber {
@@ -115,18 +162,56 @@ impl From<BlockHeader> for BlockIdAndNumber {
     }
 }
 
-/// block timestamp allowed future times
+/// block timestamp allowed future times, in milliseconds like the block timestamp.
 pub const ALLOWED_FUTURE_BLOCKTIME: u64 = 30000; // 30 second;
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, CryptoHasher, CryptoHash, JsonSchema)]
+/// Millisecond timestamps below this are suspicious, it's 2001-09-09 in milliseconds,
+/// but about 31688 years in seconds.
+pub const MIN_PLAUSIBLE_MILLIS_TIMESTAMP: u64 = 1_000_000_000_000;
+/// Length of the pow header blob: raw header hash + 12 bytes extra and nonce + difficulty.
+pub const POW_HEADER_BLOB_LENGTH: usize = 76;
+/// Offset of the little endian u32 nonce in the pow header blob. The 12 bytes extra and nonce
+/// region after the 32 bytes raw header hash holds 3 zero bytes, the 4 bytes extra at 35..39,
+/// the nonce at 39..43 and 1 zero byte.
+pub const POW_HEADER_NONCE_OFFSET: usize = 39;
+/// Offset of the 4 bytes header extra in the pow header blob, see `POW_HEADER_NONCE_OFFSET`.
+pub const POW_HEADER_EXTRA_OFFSET: usize = 35;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum BlockHeaderVerifyError {
+    #[error("Parent mismatch, expect parent hash: {expect}, got: {real}")]
+    ParentMismatch { expect: HashValue, real: HashValue },
+    #[error("Block number not sequential, parent number: {parent}, got: {real}")]
+    NumberNotSequential {
+        parent: BlockNumber,
+        real: BlockNumber,
+    },
+    #[error("Block timestamp regression, parent timestamp: {parent}, got: {real}")]
+    TimestampRegression { parent: u64, real: u64 },
+}
+
+/// The header `version` 0 is left out of both forms, so the bytes and the `id` of historical
+/// blocks are unchanged. A non-zero `version` is appended as the last field, so it round-trips
+/// and is part of the `id`. In the human readable form a missing `version` is decoded as 0.
+/// The binary form is not self-describing, so a versioned header also marks the
+/// `author_auth_key` option tag with `AUTH_KEY_VERSIONED_FLAG`, tags 2 and 3 are never written
+/// for an option, and the decoder reads the trailing `version` only when the flag is set.
+/// The `version` is not committed by the pow blob, which keeps the layout miners hash.
+/// Headers are compared and hashed by `id`.
+/// In the human readable form, the `u64` fields are encoded as decimal strings, so they don't
+/// lose precision in JSON clients, and decoded from either strings or numbers, so the JSON
+/// written before keeps working. The binary form keeps the plain integers.
+#[derive(Clone, Debug, Eq, CryptoHasher, CryptoHash, JsonSchema)]
 pub struct BlockHeader {
     #[serde(skip)]
     id: Option<HashValue>,
     /// Parent hash.
     parent_hash: HashValue,
     /// Block timestamp.
+    #[schemars(with = "String")]
     timestamp: u64,
     /// Block number.
+    #[schemars(with = "String")]
     number: BlockNumber,
     /// Block author.
     author: AccountAddress,
@@ -140,6 +225,7 @@ pub struct BlockHeader {
     /// The last transaction state_root of this block after execute.
     state_root: HashValue,
     /// Gas used for contracts execution.
+    #[schemars(with = "String")]
     gas_used: u64,
     /// Block difficulty
     #[schemars(with = "String")]
@@ -152,6 +238,9 @@ pub struct BlockHeader {
     nonce: u32,
     /// block header extra
     extra: BlockHeaderExtra,
+    /// Header format version.
+    #[serde(default)]
+    version: u32,
 }
 
 impl BlockHeader {
@@ -221,6 +310,7 @@ impl BlockHeader {
             body_hash,
             chain_id,
             extra,
+            version: 0,
         };
         header.id = Some(header.crypto_hash());
         header
@@ -239,6 +329,16 @@ impl BlockHeader {
         blob
     }
 
+    /// The pow header blob with the header `extra` written at `POW_HEADER_EXTRA_OFFSET` and
+    /// `nonce` at `POW_HEADER_NONCE_OFFSET`, the same blob consensus hashes to verify the
+    /// header, so a worker can hash candidate nonces directly.
+    pub fn pow_blob_with_nonce(&self, nonce: u32) -> Vec<u8> {
+        let mut blob = self.as_pow_header_blob();
+        blob[POW_HEADER_EXTRA_OFFSET..][..4].copy_from_slice(self.extra.as_slice());
+        blob[POW_HEADER_NONCE_OFFSET..][..4].copy_from_slice(&nonce.to_le_bytes());
+        blob
+    }
+
     pub fn id(&self) -> HashValue {
         self.id
             .expect("BlockHeader id should bean Some after init.")
@@ -264,6 +364,13 @@ impl BlockHeader {
         self.author_auth_key
     }
 
+    /// The author auth key bytes, or an empty vec if the header has no auth key.
+    pub fn author_auth_key_bytes(&self) -> Vec<u8> {
+        self.author_auth_key
+            .map(|auth_key| auth_key.to_vec())
+            .unwrap_or_default()
+    }
+
     pub fn txn_accumulator_root(&self) -> HashValue {
         self.txn_accumulator_root
     }
@@ -300,10 +407,172 @@ impl BlockHeader {
         &self.extra
     }
 
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     pub fn is_genesis(&self) -> bool {
         self.number == 0
     }
 
+    /// Diagnostic helper for time unit bugs, return true if a non-genesis block's timestamp
+    /// is too small to be milliseconds, and looks like seconds.
+    pub fn looks_like_seconds(&self) -> bool {
+        !self.is_genesis() && self.timestamp < MIN_PLAUSIBLE_MILLIS_TIMESTAMP
+    }
+
+    /// A non-genesis block executes at least the metadata transaction,
+    /// so its txn accumulator root can not be the placeholder.
+    pub fn verify_accumulator_root(&self) -> Result<()> {
+        ensure!(
+            self.is_genesis() || self.txn_accumulator_root != *ACCUMULATOR_PLACEHOLDER_HASH,
+            "Invalid block header {}: txn accumulator root of non-genesis block is placeholder.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// The parent block accumulator root of a non-genesis block must not be placeholder.
+    pub fn verify_parent_accumulator_present(&self) -> Result<()> {
+        ensure!(
+            self.is_genesis() || self.block_accumulator_root != *ACCUMULATOR_PLACEHOLDER_HASH,
+            "Invalid block header {}: parent block accumulator root of non-genesis block is placeholder.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// If the header carries an `author_auth_key`, it must derive the `author` address.
+    pub fn verify_author(&self) -> Result<()> {
+        if let Some(auth_key) = self.author_auth_key {
+            ensure!(
+                auth_key.derived_address() == self.author,
+                "Invalid block header {}: author {} mismatch with author auth key {}.",
+                self.id(),
+                self.author,
+                auth_key
+            );
+        }
+        Ok(())
+    }
+
+    /// The number of this block's child, error if it overflows.
+    pub fn child_number(&self) -> Result<BlockNumber> {
+        self.number.checked_add(1).ok_or_else(|| {
+            format_err!(
+                "Invalid block header {}: child number of {} overflows.",
+                self.id(),
+                self.number
+            )
+        })
+    }
+
+    /// The timestamp must not be more than `ALLOWED_FUTURE_BLOCKTIME` ahead of `now_millis`,
+    /// both are milliseconds.
+    pub fn verify_timestamp(&self, now_millis: u64) -> Result<()> {
+        ensure!(
+            self.timestamp <= now_millis.saturating_add(ALLOWED_FUTURE_BLOCKTIME),
+            "Invalid block header {}: timestamp {} is more than {}ms ahead of now {}.",
+            self.id(),
+            self.timestamp,
+            ALLOWED_FUTURE_BLOCKTIME,
+            now_millis
+        );
+        Ok(())
+    }
+
+    /// The difficulty must not be zero, the pow target is derived from it.
+    pub fn verify_difficulty(&self) -> Result<()> {
+        ensure!(
+            !self.difficulty.is_zero(),
+            "Invalid block header {}: difficulty is zero.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// The pow target of the header, see `pow_target`.
+    pub fn pow_target(&self) -> U256 {
+        pow_target(self.difficulty)
+    }
+
+    /// Check `pow_hash` meets the target of the header difficulty.
+    pub fn verify_pow(&self, pow_hash: U256) -> bool {
+        pow_hash <= self.pow_target()
+    }
+
+    /// A non-genesis block must have a non-zero timestamp.
+    pub fn verify_timestamp_nonzero(&self) -> Result<()> {
+        ensure!(
+            self.is_genesis() || self.timestamp != 0,
+            "Invalid block header {}: timestamp of non-genesis block is zero.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// A non-genesis block must reference a non-zero parent hash.
+    pub fn verify_parent_hash(&self) -> Result<()> {
+        ensure!(
+            self.is_genesis() || self.parent_hash != HashValue::zero(),
+            "Invalid block header {}: parent hash of non-genesis block is zero.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// Check the header follows the genesis rules of `genesis_block_header`, to reject a forged
+    /// genesis.
+    pub fn verify_genesis(&self) -> Result<()> {
+        ensure!(
+            self.is_genesis(),
+            "Invalid genesis block header {}: number is {}.",
+            self.id(),
+            self.number
+        );
+        ensure!(
+            self.author == CORE_CODE_ADDRESS && self.author_auth_key.is_none(),
+            "Invalid genesis block header {}: author should be {}.",
+            self.id(),
+            CORE_CODE_ADDRESS
+        );
+        ensure!(
+            self.block_accumulator_root == *ACCUMULATOR_PLACEHOLDER_HASH,
+            "Invalid genesis block header {}: block accumulator root should be placeholder.",
+            self.id()
+        );
+        ensure!(
+            self.gas_used == 0,
+            "Invalid genesis block header {}: gas used is {}.",
+            self.id(),
+            self.gas_used
+        );
+        Ok(())
+    }
+
+    /// Verify `child` correctly references this header as its parent.
+    pub fn verify_child(&self, child: &BlockHeader) -> Result<(), BlockHeaderVerifyError> {
+        if !constant_time_eq(&child.parent_hash, &self.id()) {
+            return Err(BlockHeaderVerifyError::ParentMismatch {
+                expect: self.id(),
+                real: child.parent_hash,
+            });
+        }
+        if self.number.checked_add(1) != Some(child.number) {
+            return Err(BlockHeaderVerifyError::NumberNotSequential {
+                parent: self.number,
+                real: child.number,
+            });
+        }
+        if child.timestamp < self.timestamp {
+            return Err(BlockHeaderVerifyError::TimestampRegression {
+                parent: self.timestamp,
+                real: child.timestamp,
+            });
+        }
+        Ok(())
+    }
+
     pub fn genesis_block_header(
         parent_hash: HashValue,
         timestamp: u64,
@@ -358,17 +627,41 @@ impl<'de> Deserialize<'de> for BlockHeader {
     where
         D: Deserializer<'de>,
     {
+        fn deserialize_u64<'de, D>(d: D) -> Result<u64, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum StringOrNumber {
+                String(String),
+                Number(u64),
+            }
+
+            if d.is_human_readable() {
+                match StringOrNumber::deserialize(d)? {
+                    StringOrNumber::String(s) => u64::from_str(&s).map_err(D::Error::custom),
+                    StringOrNumber::Number(n) => Ok(n),
+                }
+            } else {
+                u64::deserialize(d)
+            }
+        }
+
         #[derive(Deserialize)]
         #[serde(rename = "BlockHeader")]
         struct BlockHeaderData {
             parent_hash: HashValue,
+            #[serde(deserialize_with = "deserialize_u64")]
             timestamp: u64,
+            #[serde(deserialize_with = "deserialize_u64")]
             number: BlockNumber,
             author: AccountAddress,
             author_auth_key: Option<AuthenticationKey>,
             txn_accumulator_root: HashValue,
             block_accumulator_root: HashValue,
             state_root: HashValue,
+            #[serde(deserialize_with = "deserialize_u64")]
             gas_used: u64,
             difficulty: U256,
             body_hash: HashValue,
@@ -377,8 +670,147 @@ impl<'de> Deserialize<'de> for BlockHeader {
             extra: BlockHeaderExtra,
         }
 
-        let header_data = BlockHeaderData::deserialize(deserializer)?;
-        let block_header = Self::new_with_auth_key(
+        #[derive(Deserialize)]
+        #[serde(rename = "BlockHeader")]
+        struct VersionedBlockHeaderData {
+            #[serde(flatten)]
+            header_data: BlockHeaderData,
+            #[serde(default)]
+            version: u32,
+        }
+
+        /// The binary `author_auth_key`, with the flag telling whether a `version` follows.
+        struct BinaryAuthKey {
+            key: Option<AuthenticationKey>,
+            versioned: bool,
+        }
+
+        impl<'de> Deserialize<'de> for BinaryAuthKey {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                struct BinaryAuthKeyVisitor;
+
+                impl<'de> Visitor<'de> for BinaryAuthKeyVisitor {
+                    type Value = BinaryAuthKey;
+
+                    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                        f.write_str("an optional author auth key")
+                    }
+
+                    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+                    where
+                        A: SeqAccess<'de>,
+                    {
+                        let tag: u8 = next_element(&mut seq, 0, &self)?;
+                        if tag > (AUTH_KEY_VERSIONED_FLAG | 1) {
+                            return Err(A::Error::invalid_value(
+                                Unexpected::Unsigned(u64::from(tag)),
+                                &self,
+                            ));
+                        }
+                        let key = if tag & 1 == 1 {
+                            Some(next_element(&mut seq, 1, &self)?)
+                        } else {
+                            None
+                        };
+                        Ok(BinaryAuthKey {
+                            key,
+                            versioned: tag & AUTH_KEY_VERSIONED_FLAG != 0,
+                        })
+                    }
+                }
+
+                deserializer.deserialize_tuple(2, BinaryAuthKeyVisitor)
+            }
+        }
+
+        struct BinaryBlockHeaderVisitor;
+
+        impl<'de> Visitor<'de> for BinaryBlockHeaderVisitor {
+            type Value = (BlockHeaderData, u32);
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("struct BlockHeader")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let parent_hash = next_element(&mut seq, 0, &self)?;
+                let timestamp = next_element(&mut seq, 1, &self)?;
+                let number = next_element(&mut seq, 2, &self)?;
+                let author = next_element(&mut seq, 3, &self)?;
+                let auth_key: BinaryAuthKey = next_element(&mut seq, 4, &self)?;
+                let header_data = BlockHeaderData {
+                    parent_hash,
+                    timestamp,
+                    number,
+                    author,
+                    author_auth_key: auth_key.key,
+                    txn_accumulator_root: next_element(&mut seq, 5, &self)?,
+                    block_accumulator_root: next_element(&mut seq, 6, &self)?,
+                    state_root: next_element(&mut seq, 7, &self)?,
+                    gas_used: next_element(&mut seq, 8, &self)?,
+                    difficulty: next_element(&mut seq, 9, &self)?,
+                    body_hash: next_element(&mut seq, 10, &self)?,
+                    chain_id: next_element(&mut seq, 11, &self)?,
+                    nonce: next_element(&mut seq, 12, &self)?,
+                    extra: next_element(&mut seq, 13, &self)?,
+                };
+                let version = if auth_key.versioned {
+                    next_element(&mut seq, 14, &self)?
+                } else {
+                    0
+                };
+                Ok((header_data, version))
+            }
+        }
+
+        fn next_element<'de, A, T>(
+            seq: &mut A,
+            index: usize,
+            expected: &dyn serde::de::Expected,
+        ) -> Result<T, A::Error>
+        where
+            A: SeqAccess<'de>,
+            T: Deserialize<'de>,
+        {
+            seq.next_element()?
+                .ok_or_else(|| A::Error::invalid_length(index, expected))
+        }
+
+        const BINARY_FIELDS: &[&str] = &[
+            "parent_hash",
+            "timestamp",
+            "number",
+            "author",
+            "author_auth_key",
+            "txn_accumulator_root",
+            "block_accumulator_root",
+            "state_root",
+            "gas_used",
+            "difficulty",
+            "body_hash",
+            "chain_id",
+            "nonce",
+            "extra",
+            "version",
+        ];
+
+        let (header_data, version) = if deserializer.is_human_readable() {
+            let data = VersionedBlockHeaderData::deserialize(deserializer)?;
+            (data.header_data, data.version)
+        } else {
+            deserializer.deserialize_struct(
+                "BlockHeader",
+                BINARY_FIELDS,
+                BinaryBlockHeaderVisitor,
+            )?
+        };
+        let mut block_header = Self::new_with_auth_key(
             header_data.parent_hash,
             header_data.timestamp,
             header_data.number,
@@ -394,10 +826,97 @@ impl<'de> Deserialize<'de> for BlockHeader {
             header_data.nonce,
             header_data.extra,
         );
+        if version != 0 {
+            block_header.version = version;
+            block_header.id = Some(block_header.crypto_hash());
+        }
         Ok(block_header)
     }
 }
 
+/// Set on the binary `author_auth_key` option tag of a header with a non-zero `version`, see
+/// `BlockHeader`.
+const AUTH_KEY_VERSIONED_FLAG: u8 = 0b10;
+
+/// The binary `author_auth_key` of a versioned header, the option tag with
+/// `AUTH_KEY_VERSIONED_FLAG` set, followed by the key if any.
+struct BinaryAuthKey<'a>(Option<&'a AuthenticationKey>);
+
+impl Serialize for BinaryAuthKey<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        match self.0 {
+            Some(key) => {
+                tuple.serialize_element(&(AUTH_KEY_VERSIONED_FLAG | 1))?;
+                tuple.serialize_element(key)?;
+            }
+            None => tuple.serialize_element(&AUTH_KEY_VERSIONED_FLAG)?,
+        }
+        tuple.end()
+    }
+}
+
+impl PartialEq for BlockHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl std::hash::Hash for BlockHeader {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::hash::Hash::hash(&self.id(), state);
+    }
+}
+
+impl Serialize for BlockHeader {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let human_readable = serializer.is_human_readable();
+        let with_version = self.version != 0;
+        let len = if with_version { 15 } else { 14 };
+        let mut state = serializer.serialize_struct("BlockHeader", len)?;
+        state.serialize_field("parent_hash", &self.parent_hash)?;
+        if human_readable {
+            state.serialize_field("timestamp", &self.timestamp.to_string())?;
+            state.serialize_field("number", &self.number.to_string())?;
+        } else {
+            state.serialize_field("timestamp", &self.timestamp)?;
+            state.serialize_field("number", &self.number)?;
+        }
+        state.serialize_field("author", &self.author)?;
+        if with_version && !human_readable {
+            state.serialize_field(
+                "author_auth_key",
+                &BinaryAuthKey(self.author_auth_key.as_ref()),
+            )?;
+        } else {
+            state.serialize_field("author_auth_key", &self.author_auth_key)?;
+        }
+        state.serialize_field("txn_accumulator_root", &self.txn_accumulator_root)?;
+        state.serialize_field("block_accumulator_root", &self.block_accumulator_root)?;
+        state.serialize_field("state_root", &self.state_root)?;
+        if human_readable {
+            state.serialize_field("gas_used", &self.gas_used.to_string())?;
+        } else {
+            state.serialize_field("gas_used", &self.gas_used)?;
+        }
+        state.serialize_field("difficulty", &self.difficulty)?;
+        state.serialize_field("body_hash", &self.body_hash)?;
+        state.serialize_field("chain_id", &self.chain_id)?;
+        state.serialize_field("nonce", &self.nonce)?;
+        state.serialize_field("extra", &self.extra)?;
+        if with_version {
+            state.serialize_field("version", &self.version)?;
+        }
+        state.end()
+    }
+}
+
 impl Default for BlockHeader {
     fn default() -> Self {
         Self::new(
@@ -438,22 +957,28 @@ impl Sample for BlockHeader {
     }
 }
 
-#[allow(clippy::from_over_into)]
-impl Into<RawBlockHeader> for BlockHeader {
-    fn into(self) -> RawBlockHeader {
-        RawBlockHeader {
-            parent_hash: self.parent_hash,
-            timestamp: self.timestamp,
-            number: self.number,
-            author: self.author,
-            author_auth_key: self.author_auth_key,
-            accumulator_root: self.txn_accumulator_root,
-            parent_block_accumulator_root: self.block_accumulator_root,
-            state_root: self.state_root,
-            gas_used: self.gas_used,
-            difficulty: self.difficulty,
-            body_hash: self.body_hash,
-            chain_id: self.chain_id,
+impl RawBlockHeader {
+    /// The pow target of the header, see `pow_target`.
+    pub fn pow_target(&self) -> U256 {
+        pow_target(self.difficulty)
+    }
+}
+
+impl From<BlockHeader> for RawBlockHeader {
+    fn from(header: BlockHeader) -> Self {
+        Self {
+            parent_hash: header.parent_hash,
+            timestamp: header.timestamp,
+            number: header.number,
+            author: header.author,
+            author_auth_key: header.author_auth_key,
+            accumulator_root: header.txn_accumulator_root,
+            parent_block_accumulator_root: header.block_accumulator_root,
+            state_root: header.state_root,
+            gas_used: header.gas_used,
+            difficulty: header.difficulty,
+            body_hash: header.body_hash,
+            chain_id: header.chain_id,
         }
     }
 }
@@ -580,10 +1105,35 @@ impl BlockHeaderBuilder {
         self
     }
 
+    /// A non-zero version is part of the header id, see `BlockHeader`.
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.buffer.version = version;
+        self
+    }
+
     pub fn build(mut self) -> BlockHeader {
         self.buffer.id = Some(self.buffer.crypto_hash());
         self.buffer
     }
+
+    /// Like `build`, but fails if a field that has no meaningful default is still zero:
+    /// the timestamp and body hash, and the parent hash of a non-genesis header.
+    pub fn try_build(self) -> Result<BlockHeader> {
+        ensure!(
+            self.buffer.timestamp != 0,
+            "block header timestamp is not set"
+        );
+        ensure!(
+            self.buffer.body_hash != HashValue::zero(),
+            "block header body hash is not set"
+        );
+        ensure!(
+            self.buffer.number == 0 || self.buffer.parent_hash != HashValue::zero(),
+            "block header parent hash is not set for block {}",
+            self.buffer.number
+        );
+        Ok(self.build())
+    }
 }
 
 #[derive(
@@ -597,16 +1147,54 @@ pub struct BlockBody {
 }
 
 impl BlockBody {
+    /// Construct a body without any check, the transactions may contain duplicates,
+    /// use `new_checked` for transactions from untrusted source.
     pub fn new(transactions: Vec<SignedUserTransaction>, uncles: Option<Vec<BlockHeader>>) -> Self {
         Self {
             transactions,
             uncles,
         }
     }
+    /// Same as `new`, but error if two transactions have the same id.
+    pub fn new_checked(
+        transactions: Vec<SignedUserTransaction>,
+        uncles: Option<Vec<BlockHeader>>,
+    ) -> Result<Self> {
+        let mut txn_ids = HashSet::with_capacity(transactions.len());
+        for txn in &transactions {
+            ensure!(
+                txn_ids.insert(txn.id()),
+                "Duplicate transaction {} in block body.",
+                txn.id()
+            );
+        }
+        Ok(Self::new(transactions, uncles))
+    }
+
     pub fn get_txn(&self, index: usize) -> Option<&SignedUserTransaction> {
         self.transactions.get(index)
     }
 
+    /// Append a transaction to the body, the uncles are kept unchanged.
+    pub fn push_transaction(&mut self, txn: SignedUserTransaction) {
+        self.transactions.push(txn);
+    }
+
+    /// Keep only the transactions for which `f` returns true, like `Vec::retain`.
+    /// Mutating the body invalidates any `body_hash` computed from it before,
+    /// use `hash` to get the new body hash.
+    pub fn retain_transactions<F>(&mut self, f: F)
+    where
+        F: FnMut(&SignedUserTransaction) -> bool,
+    {
+        self.transactions.retain(f);
+    }
+
+    /// Append transactions to the body, the uncles are kept unchanged.
+    pub fn extend_transactions(&mut self, txns: impl IntoIterator<Item = SignedUserTransaction>) {
+        self.transactions.extend(txns);
+    }
+
     /// Just for test
     pub fn new_empty() -> BlockBody {
         BlockBody {
@@ -615,9 +1203,38 @@ impl BlockBody {
         }
     }
 
+    /// The body hash is a structural hash over the BCS encoding of the whole body, which starts
+    /// with the transaction count, so it can't be extended incrementally when a transaction is
+    /// appended, and the fields are public, so the body can't memoize it either.
+    /// Use `BlockBodyBuilder::hash_cached` to memoize it while building a body.
     pub fn hash(&self) -> HashValue {
         self.crypto_hash()
     }
+
+    /// The BCS encoded body compressed by deflate.
+    pub fn compress(&self) -> Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bcs_ext::to_bytes(self)?)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Select at most `max` uncles for a block at `block_number` from `candidates`,
+    /// preferring the freshest ones (smallest `block_number - uncle.number`).
+    /// Candidates that are not below `block_number` are ignored, and duplicated ids are only taken once.
+    pub fn select_best_uncles(
+        mut candidates: Vec<BlockHeader>,
+        block_number: BlockNumber,
+        max: usize,
+    ) -> Vec<BlockHeader> {
+        candidates.retain(|uncle| uncle.number() < block_number);
+        candidates.sort_by_key(|uncle| block_number.saturating_sub(uncle.number()));
+        let mut ids = HashSet::new();
+        candidates
+            .into_iter()
+            .filter(|uncle| ids.insert(uncle.id()))
+            .take(max)
+            .collect()
+    }
 }
 
 #[allow(clippy::from_over_into)]
@@ -646,6 +1263,58 @@ impl Sample for BlockBody {
     }
 }
 
+/// Build a `BlockBody` transaction by transaction, with the body hash memoized until the body
+/// is changed by the builder.
+#[derive(Clone, Debug, Default)]
+pub struct BlockBodyBuilder {
+    body: BlockBody,
+    hash: Option<HashValue>,
+}
+
+impl BlockBodyBuilder {
+    pub fn new(uncles: Option<Vec<BlockHeader>>) -> Self {
+        Self {
+            body: BlockBody::new(vec![], uncles),
+            hash: None,
+        }
+    }
+
+    pub fn transactions(&self) -> &[SignedUserTransaction] {
+        self.body.transactions.as_slice()
+    }
+
+    /// Append a transaction to the body, it clears the memoized hash.
+    pub fn push_transaction(&mut self, txn: SignedUserTransaction) {
+        self.body.push_transaction(txn);
+        self.hash = None;
+    }
+
+    /// Append transactions to the body, it clears the memoized hash.
+    pub fn extend_transactions(&mut self, txns: impl IntoIterator<Item = SignedUserTransaction>) {
+        self.body.extend_transactions(txns);
+        self.hash = None;
+    }
+
+    /// The hash of the body built so far, it's computed on the first call after a change.
+    pub fn hash_cached(&mut self) -> HashValue {
+        let body = &self.body;
+        *self.hash.get_or_insert_with(|| body.hash())
+    }
+
+    pub fn build(self) -> BlockBody {
+        self.body
+    }
+}
+
+/// Everything the block reward calculation consumes from a block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RewardInputs {
+    pub author: AccountAddress,
+    pub uncle_authors: Vec<AccountAddress>,
+    pub gas_used: u64,
+    pub block_number: BlockNumber,
+}
+
 /// A block, encoded as it is on the block chain.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize, CryptoHasher, CryptoHash)]
 pub struct Block {
@@ -676,6 +1345,12 @@ impl Block {
         self.body.transactions.as_slice()
     }
 
+    /// Iterate the transactions of the block body, uncles only carry headers so
+    /// no transaction of uncles is yielded.
+    pub fn iter_transactions(&self) -> impl ExactSizeIterator<Item = &SignedUserTransaction> {
+        self.body.transactions.iter()
+    }
+
     pub fn uncles(&self) -> Option<&[BlockHeader]> {
         match &self.body.uncles {
             Some(uncles) => Some(uncles.as_slice()),
@@ -683,12 +1358,295 @@ impl Block {
         }
     }
 
+    pub fn txn_count(&self) -> usize {
+        self.body.transactions.len()
+    }
+
+    /// The number of uncles, 0 if the block has no uncles.
+    pub fn uncle_count(&self) -> usize {
+        self.body.uncles.as_ref().map_or(0, |uncles| uncles.len())
+    }
+
+    /// The number of entries in the body, the transactions plus the uncle headers.
+    pub fn txn_and_uncle_count(&self) -> usize {
+        self.txn_count().saturating_add(self.uncle_count())
+    }
+
+    /// The number of transactions the block carries once its uncles are included.
+    /// Uncles only contribute headers, so this equals `txn_count`.
+    pub fn total_txn_count_with_uncles(&self) -> usize {
+        self.txn_count()
+    }
+
+    /// The weight this block adds to the chain for fork choice, it's the block difficulty,
+    /// the same amount the chain adds to the parent's `total_difficulty`, so the sum of the
+    /// block weights is `ExecutedBlock::cumulative_weight`. Uncles add no weight, it equals
+    /// `effective_difficulty(0, 1)`.
+    pub fn weight(&self) -> U256 {
+        self.header.difficulty
+    }
+
+    pub fn reward_inputs(&self) -> RewardInputs {
+        RewardInputs {
+            author: self.header.author,
+            uncle_authors: self
+                .uncles()
+                .map(|uncles| uncles.iter().map(|uncle| uncle.author).collect())
+                .unwrap_or_default(),
+            gas_used: self.header.gas_used,
+            block_number: self.header.number,
+        }
+    }
+
     pub fn uncle_ids(&self) -> Vec<HashValue> {
         self.uncles()
             .map(|uncles| uncles.iter().map(|header| header.id()).collect())
             .unwrap_or_default()
     }
 
+    /// Check every uncle is at most `max_gap` blocks below this block.
+    pub fn verify_uncle_recency(&self, max_gap: u64) -> Result<()> {
+        if let Some(uncles) = self.uncles() {
+            for uncle in uncles {
+                let gap = self.header.number.saturating_sub(uncle.number);
+                ensure!(
+                    gap <= max_gap,
+                    "Invalid block {}: uncle {} at number {} is {} blocks below, exceeds the window {}.",
+                    self.id(),
+                    uncle.id(),
+                    uncle.number,
+                    gap,
+                    max_gap
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the structural header checks on every uncle: difficulty, parent hash and timestamp.
+    /// The error names the offending uncle.
+    pub fn verify_uncles_structural(&self) -> Result<()> {
+        if let Some(uncles) = self.uncles() {
+            for uncle in uncles {
+                uncle
+                    .verify_difficulty()
+                    .and_then(|_| uncle.verify_parent_hash())
+                    .and_then(|_| uncle.verify_timestamp_nonzero())
+                    .map_err(|e| {
+                        format_err!("Invalid block {}: uncle {} {}", self.id(), uncle.id(), e)
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every uncle must be produced on the same chain as this block.
+    pub fn verify_uncle_chain_ids(&self) -> Result<()> {
+        if let Some(uncles) = self.uncles() {
+            for uncle in uncles {
+                ensure!(
+                    uncle.chain_id == self.header.chain_id,
+                    "Invalid block {}: uncle {} chain id {} mismatch with block chain id {}.",
+                    self.id(),
+                    uncle.id(),
+                    uncle.chain_id,
+                    self.header.chain_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every transaction and uncle of the block is of the header's chain id,
+    /// error with the first mismatch, to keep foreign chain transactions out of the block.
+    pub fn verify_chain_id(&self) -> Result<()> {
+        self.verify_transactions_chain_id()?;
+        self.verify_uncle_chain_ids()
+    }
+
+    /// The block's weight with its uncles' contributions,
+    /// `difficulty + difficulty * uncle_count * uncle_weight_numerator / denominator`.
+    /// Uncles are not counted if `denominator` is zero.
+    /// It's for weighting policies other than the chain's, `total_difficulty` doesn't count
+    /// uncles, see `weight`.
+    pub fn effective_difficulty(&self, uncle_weight_numerator: u32, denominator: u32) -> U256 {
+        let difficulty = self.header.difficulty;
+        let uncle_count = self.uncles().map(|uncles| uncles.len()).unwrap_or(0);
+        let uncle_difficulty = difficulty
+            .saturating_mul(U256::from(uncle_count))
+            .saturating_mul(U256::from(uncle_weight_numerator))
+            .checked_div(U256::from(denominator))
+            .unwrap_or_else(U256::zero);
+        difficulty.saturating_add(uncle_difficulty)
+    }
+
+    /// Distance between this block and each of its uncles, `self.number - uncle.number`.
+    pub fn uncle_distances(&self) -> Vec<u64> {
+        self.uncles()
+            .map(|uncles| {
+                uncles
+                    .iter()
+                    .map(|uncle| self.header.number.saturating_sub(uncle.number))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Check whether this block and `other` have a common ancestor within `depth` parents,
+    /// the blocks themselves are included. Parents are resolved by `lookup`,
+    /// and the walk stops early when a parent can not be found.
+    pub fn shares_ancestor_within(
+        &self,
+        other: &BlockHeader,
+        depth: u64,
+        lookup: impl Fn(HashValue) -> Option<BlockHeader>,
+    ) -> bool {
+        let ancestors = |header: &BlockHeader| {
+            let mut ids = vec![header.id()];
+            let mut current = header.clone();
+            for _ in 0..depth {
+                if current.is_genesis() {
+                    break;
+                }
+                match lookup(current.parent_hash()) {
+                    Some(parent) => {
+                        ids.push(parent.id());
+                        current = parent;
+                    }
+                    None => break,
+                }
+            }
+            ids
+        };
+        let self_ancestors: HashSet<HashValue> = ancestors(&self.header).into_iter().collect();
+        ancestors(other)
+            .iter()
+            .any(|id| self_ancestors.contains(id))
+    }
+
+    /// Check all transactions in the block share the header's chain id,
+    /// the error reports the index of the first mismatched transaction.
+    pub fn verify_transactions_chain_id(&self) -> Result<()> {
+        let chain_id = self.header.chain_id();
+        if let Some((index, txn)) = self
+            .transactions()
+            .iter()
+            .enumerate()
+            .find(|(_, txn)| txn.chain_id() != chain_id)
+        {
+            bail!(
+                "Invalid block {}: transaction at index {} has chain id {}, expect {}.",
+                self.id(),
+                index,
+                txn.chain_id(),
+                chain_id
+            );
+        }
+        Ok(())
+    }
+
+    /// BCS encode the block directly into `writer`, without an intermediate buffer.
+    pub fn encode_to_writer<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        Ok(bcs_ext::serialize_into(writer, self)?)
+    }
+
+    /// Decode a BCS encoded block from `reader`, the reader is read to the end.
+    pub fn decode_from_reader<R: std::io::Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        bcs_ext::from_bytes(&bytes)
+    }
+
+    /// The BCS encoded size of the block, it's counted without encoding the block into a buffer,
+    /// so a block producer can check it against the block size limit cheaply.
+    pub fn encoded_size(&self) -> Result<usize> {
+        Ok(bcs_ext::serialized_size(self)?)
+    }
+
+    /// Same as `encoded_size`.
+    pub fn serialized_size(&self) -> Result<usize> {
+        self.encoded_size()
+    }
+
+    /// The size of the encoded header plus the compressed body, see `BlockBody::compress`.
+    pub fn compressed_size(&self) -> Result<usize> {
+        let header_size = bcs_ext::serialized_size(&self.header)?;
+        let body_size = self.body.compress()?.len();
+        header_size
+            .checked_add(body_size)
+            .ok_or_else(|| format_err!("Compressed size of block {} overflows.", self.id()))
+    }
+
+    /// Hash of the concatenated ids of the block transactions in order,
+    /// a stable fingerprint of the body for indexing, unrelated to the txn accumulator.
+    pub fn transaction_id_commitment(&self) -> HashValue {
+        let bytes: Vec<u8> = self
+            .body
+            .transactions
+            .iter()
+            .flat_map(|txn| txn.id().to_vec())
+            .collect();
+        HashValue::sha3_256_of(&bytes)
+    }
+
+    /// Check the header's `body_hash` matches the body.
+    pub fn verify_body_hash(&self) -> Result<()> {
+        let body_hash = self.body.hash();
+        ensure!(
+            constant_time_eq(&self.header.body_hash, &body_hash),
+            "Invalid block {}: body hash {} mismatch with header body hash {}.",
+            self.id(),
+            body_hash,
+            self.header.body_hash
+        );
+        Ok(())
+    }
+
+    /// Check the block follows the genesis rules of `Block::genesis_block`.
+    pub fn verify_genesis(&self) -> Result<()> {
+        self.header.verify_genesis()?;
+        ensure!(
+            self.uncles()
+                .map(|uncles| uncles.is_empty())
+                .unwrap_or(true),
+            "Invalid genesis block {}: genesis block can not have uncles.",
+            self.id()
+        );
+        Ok(())
+    }
+
+    /// Check the block is self consistent: the header id matches the header,
+    /// the body hash matches the body, and genesis rules if it's a genesis block.
+    /// A deserialized block should pass this before being trusted.
+    pub fn verify_self(&self) -> Result<()> {
+        let header_hash = self.header.crypto_hash();
+        ensure!(
+            header_hash == self.header.id(),
+            "Invalid block {}: id mismatch with header hash {}.",
+            self.id(),
+            header_hash
+        );
+        self.verify_body_hash()?;
+        if self.header.is_genesis() {
+            self.verify_genesis()?;
+        }
+        Ok(())
+    }
+
+    /// Split the block into a `BlockSummary` with the header and uncles, and the transactions.
+    pub fn split_summary(self) -> (BlockSummary, Vec<SignedUserTransaction>) {
+        let summary = BlockSummary {
+            block_header: self.header,
+            uncles: self.body.uncles.unwrap_or_default(),
+        };
+        (summary, self.body.transactions)
+    }
+
+    /// Build a `BlockTemplate` from this block for re-mining under `strategy`, without re-execution.
+    pub fn to_template(&self, strategy: ConsensusStrategy) -> BlockTemplate {
+        BlockTemplate::from_block(self.clone(), strategy)
+    }
+
     pub fn into_inner(self) -> (BlockHeader, BlockBody) {
         (self.header, self.body)
     }
@@ -718,6 +1676,49 @@ impl Block {
         }
     }
 
+    /// Same as `to_metadata`, but check the author and author auth key are consistent first.
+    pub fn to_metadata_checked(&self, parent_gas_used: u64) -> Result<BlockMetadata> {
+        self.header.verify_author()?;
+        Ok(self.to_metadata(parent_gas_used))
+    }
+
+    /// Same as `genesis_block`, but verify the result. The genesis transaction must be executed,
+    /// so `accumulator_root` and `state_root` can not be placeholders, and the block must pass
+    /// `verify_genesis` and `verify_body_hash`.
+    pub fn genesis_block_checked(
+        parent_hash: HashValue,
+        timestamp: u64,
+        accumulator_root: HashValue,
+        state_root: HashValue,
+        difficulty: U256,
+        genesis_txn: SignedUserTransaction,
+    ) -> Result<Self> {
+        ensure!(
+            accumulator_root != *ACCUMULATOR_PLACEHOLDER_HASH,
+            "Invalid genesis block: accumulator root is placeholder."
+        );
+        ensure!(
+            state_root != *SPARSE_MERKLE_PLACEHOLDER_HASH,
+            "Invalid genesis block: state root is placeholder."
+        );
+        let block = Self::genesis_block(
+            parent_hash,
+            timestamp,
+            accumulator_root,
+            state_root,
+            difficulty,
+            genesis_txn,
+        );
+        ensure!(
+            block.transactions().len() == 1,
+            "Invalid genesis block {}: should contain exactly one genesis transaction.",
+            block.id()
+        );
+        block.verify_genesis()?;
+        block.verify_body_hash()?;
+        Ok(block)
+    }
+
     pub fn to_metadata(&self, parent_gas_used: u64) -> BlockMetadata {
         let uncles = self
             .body
@@ -772,6 +1773,57 @@ impl Sample for Block {
     }
 }
 
+/// Sum of `header.difficulty` across `blocks`, error on overflow.
+pub fn sum_difficulty(blocks: &[Block]) -> Result<U256> {
+    blocks.iter().try_fold(U256::zero(), |sum, block| {
+        sum.checked_add(block.header.difficulty).ok_or_else(|| {
+            format_err!("Sum of block difficulty overflows at block {}.", block.id())
+        })
+    })
+}
+
+/// The boundary a pow hash must not exceed for `difficulty`, it's `U256::MAX / difficulty`.
+/// A zero difficulty is invalid, see `BlockHeader::verify_difficulty`, it's treated as no
+/// boundary and gets `U256::MAX`.
+pub fn pow_target(difficulty: U256) -> U256 {
+    U256::max_value()
+        .checked_div(difficulty)
+        .unwrap_or_else(U256::max_value)
+}
+
+/// Parse a blob produced by `as_pow_header_blob` into the raw header hash and difficulty.
+/// The extra and nonce region must be zero, i.e. the blob has not been solved yet.
+pub fn parse_pow_header_blob(blob: &[u8]) -> Result<(HashValue, U256)> {
+    ensure!(
+        blob.len() == POW_HEADER_BLOB_LENGTH,
+        "Invalid pow header blob length {}, expect {}.",
+        blob.len(),
+        POW_HEADER_BLOB_LENGTH
+    );
+    let (hash_bytes, rest) = blob.split_at(HashValue::LENGTH);
+    let (extend_and_nonce, diff_bytes) = rest.split_at(12);
+    ensure!(
+        extend_and_nonce.iter().all(|b| *b == 0),
+        "Invalid pow header blob: extra and nonce region is not zero."
+    );
+    let raw_header_hash = HashValue::from_slice(hash_bytes)?;
+    Ok((raw_header_hash, U256::from_big_endian(diff_bytes)))
+}
+
+/// Compare two hashes without short-circuiting at the first different byte, so the time it
+/// takes doesn't leak the length of the common prefix.
+/// Block ids, parent hashes and body hashes are public, the constant time comparison of them
+/// is only defensive. Callers comparing hashes derived from secret material, such as
+/// authentication keys not yet published on chain, should use this instead of `==`.
+pub fn constant_time_eq(a: &HashValue, b: &HashValue) -> bool {
+    let a: &[u8; HashValue::LENGTH] = a.as_ref();
+    let b: &[u8; HashValue::LENGTH] = b.as_ref();
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
 /// `BlockInfo` is the object we store in the storage. It consists of the
 /// block as well as the execution result of this block.
 #[derive(
@@ -823,6 +1875,49 @@ impl BlockInfo {
     pub fn block_id(&self) -> &HashValue {
         &self.block_id
     }
+
+    /// Prove the block at `leaf_index`, i.e. the block number, is an ancestor of this block,
+    /// by the block accumulator in `store`. The proof verifies against
+    /// `block_accumulator_info.accumulator_root`.
+    pub fn get_ancestor_proof(
+        &self,
+        store: Arc<dyn AccumulatorTreeStore>,
+        leaf_index: u64,
+    ) -> Result<AccumulatorProof> {
+        ensure!(
+            leaf_index < self.block_accumulator_info.num_leaves,
+            "No block at index {} in the block accumulator of block {}.",
+            leaf_index,
+            self.block_id
+        );
+        let accumulator =
+            MerkleAccumulator::new_with_info(self.block_accumulator_info.clone(), store);
+        accumulator.get_proof(leaf_index)?.ok_or_else(|| {
+            format_err!(
+                "No block at index {} in the block accumulator of block {}.",
+                leaf_index,
+                self.block_id
+            )
+        })
+    }
+
+    /// Check this block info belongs to `header`, and its total difficulty includes the header's.
+    pub fn verify_against_header(&self, header: &BlockHeader) -> Result<()> {
+        ensure!(
+            self.block_id == header.id(),
+            "Block info of block {} mismatch with header {}.",
+            self.block_id,
+            header.id()
+        );
+        ensure!(
+            self.total_difficulty >= header.difficulty(),
+            "Block info of block {}: total difficulty {} is less than block difficulty {}.",
+            self.block_id,
+            self.total_difficulty,
+            header.difficulty()
+        );
+        Ok(())
+    }
 }
 
 impl Sample for BlockInfo {
@@ -897,6 +1992,26 @@ impl BlockTemplate {
         }
     }
 
+    /// Build a template from an existing block, for re-mining it under `strategy`.
+    pub fn from_block(block: Block, strategy: ConsensusStrategy) -> Self {
+        let (header, body) = block.into_inner();
+        Self {
+            parent_hash: header.parent_hash,
+            timestamp: header.timestamp,
+            number: header.number,
+            author: header.author,
+            txn_accumulator_root: header.txn_accumulator_root,
+            block_accumulator_root: header.block_accumulator_root,
+            state_root: header.state_root,
+            gas_used: header.gas_used,
+            body_hash: header.body_hash,
+            body,
+            chain_id: header.chain_id,
+            difficulty: header.difficulty,
+            strategy,
+        }
+    }
+
     pub fn into_block(self, nonce: u32, extra: BlockHeaderExtra) -> Block {
         let header = BlockHeader::new(
             self.parent_hash,
@@ -919,6 +2034,53 @@ impl BlockTemplate {
         }
     }
 
+    /// Check the template's `body_hash` matches the body, and the difficulty is not zero.
+    pub fn verify(&self) -> Result<()> {
+        let body_hash = self.body.hash();
+        ensure!(
+            constant_time_eq(&body_hash, &self.body_hash),
+            "Invalid block template: body hash {} mismatch with template body hash {}.",
+            body_hash,
+            self.body_hash
+        );
+        ensure!(
+            !self.difficulty.is_zero(),
+            "Invalid block template: difficulty is zero."
+        );
+        Ok(())
+    }
+
+    /// Check the template has at most `max` uncles, and every uncle is below the template number.
+    pub fn verify_uncles(&self, max: usize) -> Result<()> {
+        if let Some(uncles) = &self.body.uncles {
+            ensure!(
+                uncles.len() <= max,
+                "Invalid block template: {} uncles exceeds the limit {}.",
+                uncles.len(),
+                max
+            );
+            for uncle in uncles {
+                ensure!(
+                    uncle.number < self.number,
+                    "Invalid block template: uncle {} number {} is not below template number {}.",
+                    uncle.id(),
+                    uncle.number,
+                    self.number
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `into_block`, but `verify` the template before sealing, and check the chain id of
+    /// the sealed block with `Block::verify_chain_id`.
+    pub fn into_block_checked(self, nonce: u32, extra: BlockHeaderExtra) -> Result<Block> {
+        self.verify()?;
+        let block = self.into_block(nonce, extra);
+        block.verify_chain_id()?;
+        Ok(block)
+    }
+
     pub fn as_raw_block_header(&self) -> RawBlockHeader {
         RawBlockHeader {
             parent_hash: self.parent_hash,
@@ -969,21 +2131,49 @@ impl BlockTemplate {
     }
 }
 
-#[derive(Clone, Debug, Hash, Serialize, Deserialize, CryptoHasher, CryptoHash)]
+/// Two executed blocks are equal if they have the same block id and block info id,
+/// the ids cover all the content, so the transactions are not compared one by one.
+/// `Hash` is consistent with it. The execution only `gas_used_per_txn` is not compared.
+#[derive(Clone, Debug, Serialize, Deserialize, CryptoHasher, CryptoHash)]
 pub struct ExecutedBlock {
     pub block: Block,
     pub block_info: BlockInfo,
+    /// Gas used by each executed transaction in execution order, the block metadata transaction
+    /// first. It's only carried from execution, and not serialized.
+    #[serde(skip)]
+    pub gas_used_per_txn: Option<Vec<u64>>,
 }
 
 impl ExecutedBlock {
     pub fn new(block: Block, block_info: BlockInfo) -> Self {
-        ExecutedBlock { block, block_info }
+        ExecutedBlock {
+            block,
+            block_info,
+            gas_used_per_txn: None,
+        }
+    }
+
+    pub fn with_gas_used_per_txn(mut self, gas_used_per_txn: Vec<u64>) -> Self {
+        self.gas_used_per_txn = Some(gas_used_per_txn);
+        self
+    }
+
+    /// The gas used by each executed transaction, `None` if the block is not executed by this
+    /// instance, e.g. it's loaded from storage.
+    pub fn gas_used_per_txn(&self) -> Option<&[u64]> {
+        self.gas_used_per_txn.as_deref()
     }
 
     pub fn total_difficulty(&self) -> U256 {
         self.block_info.total_difficulty
     }
 
+    /// The cumulative weight of the chain ending at this block, used by fork choice.
+    /// It's the total difficulty in `block_info`, the uncle difficulty is not accumulated.
+    pub fn cumulative_weight(&self) -> U256 {
+        self.block_info.total_difficulty
+    }
+
     pub fn block(&self) -> &Block {
         &self.block
     }
@@ -997,7 +2187,22 @@ impl ExecutedBlock {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl PartialEq for ExecutedBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.block.id() == other.block.id() && self.block_info.id() == other.block_info.id()
+    }
+}
+
+impl Eq for ExecutedBlock {}
+
+impl std::hash::Hash for ExecutedBlock {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::hash::Hash::hash(&self.block.id(), state);
+        std::hash::Hash::hash(&self.block_info.id(), state);
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, CryptoHasher, CryptoHash)]
 pub struct BlockSummary {
     pub block_header: BlockHeader,
     pub uncles: Vec<BlockHeader>,
@@ -1029,7 +2234,7 @@ impl Into<(BlockHeader, Vec<BlockHeader>)> for BlockSummary {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, CryptoHasher, CryptoHash)]
 pub struct UncleSummary {
     /// total uncle
     pub uncles: u64,
@@ -1054,9 +2259,26 @@ impl UncleSummary {
             time_avg,
         }
     }
+
+    /// Aggregate the uncles of `summaries`, the distance of an uncle is the number of the block
+    /// containing it minus the uncle number, the time is the timestamp of the containing block
+    /// minus the uncle timestamp.
+    pub fn from_summaries(summaries: &[BlockSummary]) -> Self {
+        let (mut uncles, mut sum, mut time_sum) = (0u64, 0u64, 0u64);
+        for summary in summaries {
+            let header = summary.header();
+            for uncle in summary.uncles() {
+                uncles = uncles.saturating_add(1);
+                sum = sum.saturating_add(header.number().saturating_sub(uncle.number()));
+                time_sum =
+                    time_sum.saturating_add(header.timestamp().saturating_sub(uncle.timestamp()));
+            }
+        }
+        Self::new(uncles, sum, time_sum)
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, CryptoHasher, CryptoHash)]
 pub struct EpochUncleSummary {
     /// epoch number
     pub epoch: u64,
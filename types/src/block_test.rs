@@ -0,0 +1,1323 @@
+// Copyright (c) The Starcoin Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+#![allow(clippy::integer_arithmetic)]
+
+use crate::account_address::AccountAddress;
+use crate::block::{
+    constant_time_eq, parse_pow_header_blob, pow_target, sum_difficulty, AccumulatorInfo, Block,
+    BlockBody, BlockBodyBuilder, BlockHeader, BlockHeaderBuilder, BlockHeaderExtra,
+    BlockHeaderVerifyError, BlockIdAndNumber, BlockInfo, BlockNumber, BlockSummary, BlockTemplate,
+    EpochUncleSummary, ExecutedBlock, RawBlockHeader, RewardInputs, UncleSummary,
+    ALLOWED_FUTURE_BLOCKTIME, POW_HEADER_BLOB_LENGTH, POW_HEADER_EXTRA_OFFSET,
+    POW_HEADER_NONCE_OFFSET,
+};
+use crate::genesis_config::{ChainId, ConsensusStrategy};
+use crate::transaction::authenticator::AuthenticationKey;
+use crate::transaction::{RawUserTransaction, Script, SignedUserTransaction, TransactionPayload};
+use crate::U256;
+use bcs_ext::Sample;
+use starcoin_accumulator::tree_store::mock::MockAccumulatorStore;
+use starcoin_accumulator::{Accumulator, MerkleAccumulator};
+use starcoin_crypto::hash::{
+    PlainCryptoHash, ACCUMULATOR_PLACEHOLDER_HASH, SPARSE_MERKLE_PLACEHOLDER_HASH,
+};
+use starcoin_crypto::HashValue;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn txn_with_chain_id(chain_id: ChainId) -> SignedUserTransaction {
+    let raw_txn = RawUserTransaction::new_with_default_gas_token(
+        AccountAddress::random(),
+        0,
+        TransactionPayload::Script(Script::new(vec![], vec![], vec![])),
+        0,
+        0,
+        u64::max_value(),
+        chain_id,
+    );
+    SignedUserTransaction::new(raw_txn, SignedUserTransaction::mock().authenticator())
+}
+
+fn header_with_number(number: BlockNumber) -> BlockHeader {
+    BlockHeaderBuilder::random().with_number(number).build()
+}
+
+#[test]
+fn test_select_best_uncles() {
+    let uncle_9 = header_with_number(9);
+    let uncle_8 = header_with_number(8);
+    let uncle_7 = header_with_number(7);
+    let uncle_5 = header_with_number(5);
+    let candidates = vec![
+        uncle_5,
+        uncle_9.clone(),
+        uncle_7,
+        uncle_9.clone(),
+        header_with_number(10),
+        uncle_8.clone(),
+    ];
+    let selected = BlockBody::select_best_uncles(candidates, 10, 2);
+    assert_eq!(selected, vec![uncle_9, uncle_8]);
+}
+
+#[test]
+fn test_uncle_distances() {
+    let uncles = vec![header_with_number(8), header_with_number(9)];
+    let block = Block::new(header_with_number(10), BlockBody::new(vec![], Some(uncles)));
+    assert_eq!(block.uncle_distances(), vec![2, 1]);
+
+    let block = Block::new(header_with_number(10), BlockBody::new_empty());
+    assert!(block.uncle_distances().is_empty());
+}
+
+#[test]
+fn test_verify_parent_hash() {
+    let genesis = BlockHeader::sample();
+    assert_eq!(genesis.parent_hash(), HashValue::zero());
+    assert!(genesis.verify_parent_hash().is_ok());
+
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_parent_hash(HashValue::zero())
+        .build();
+    assert!(header.verify_parent_hash().is_err());
+
+    let header = header_with_number(1);
+    assert!(header.verify_parent_hash().is_ok());
+}
+
+#[test]
+fn test_block_body_incremental_build() {
+    let txns: Vec<SignedUserTransaction> = (0..4).map(|_| SignedUserTransaction::mock()).collect();
+    let uncles = Some(vec![header_with_number(1)]);
+    let full_body = BlockBody::new(txns.clone(), uncles.clone());
+
+    let mut body = BlockBody::new(vec![], uncles);
+    body.push_transaction(txns[0].clone());
+    body.extend_transactions(txns[1..].iter().cloned());
+    assert_eq!(body, full_body);
+    assert_eq!(body.hash(), full_body.hash());
+}
+
+#[test]
+fn test_shares_ancestor_within() {
+    let child_of = |parent: &BlockHeader| {
+        BlockHeaderBuilder::random()
+            .with_parent_hash(parent.id())
+            .with_number(parent.number() + 1)
+            .build()
+    };
+    // genesis <- a <- b <- c
+    //              \_ b1
+    let genesis = header_with_number(0);
+    let a = child_of(&genesis);
+    let b = child_of(&a);
+    let c = child_of(&b);
+    let b1 = child_of(&a);
+    let headers: HashMap<HashValue, BlockHeader> = vec![&genesis, &a, &b, &c, &b1]
+        .into_iter()
+        .map(|header| (header.id(), header.clone()))
+        .collect();
+    let lookup = |id: HashValue| headers.get(&id).cloned();
+
+    let block_c = Block::new(c, BlockBody::new_empty());
+    assert!(block_c.shares_ancestor_within(&b1, 2, lookup));
+    assert!(!block_c.shares_ancestor_within(&b1, 1, lookup));
+    assert!(block_c.shares_ancestor_within(&block_c.header, 0, lookup));
+}
+
+#[test]
+fn test_looks_like_seconds() {
+    let seconds = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_timestamp(1_621_311_100)
+        .build();
+    assert!(seconds.looks_like_seconds());
+    let millis = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_timestamp(1_621_311_100_000)
+        .build();
+    assert!(!millis.looks_like_seconds());
+    let genesis = BlockHeaderBuilder::random()
+        .with_number(0)
+        .with_timestamp(1_621_311_100)
+        .build();
+    assert!(!genesis.looks_like_seconds());
+}
+
+#[test]
+fn test_verify_transactions_chain_id() {
+    let chain_id = ChainId::new(1);
+    let header = BlockHeaderBuilder::random().with_chain_id(chain_id).build();
+    let txns = vec![txn_with_chain_id(chain_id), txn_with_chain_id(chain_id)];
+    let block = Block::new(header.clone(), txns.clone());
+    assert!(block.verify_transactions_chain_id().is_ok());
+
+    let mut bad_txns = txns;
+    bad_txns.insert(1, txn_with_chain_id(ChainId::test()));
+    let block = Block::new(header, bad_txns);
+    let err = block.verify_transactions_chain_id().unwrap_err();
+    assert!(err.to_string().contains("index 1"));
+}
+
+#[test]
+fn test_to_metadata_checked() {
+    let auth_key = AuthenticationKey::random();
+    let header = BlockHeaderBuilder::random()
+        .with_author(auth_key.derived_address())
+        .with_author_auth_key(Some(auth_key))
+        .build();
+    let block = Block::new(header, BlockBody::new_empty());
+    let metadata = block.to_metadata_checked(0).unwrap();
+    assert_eq!(metadata, block.to_metadata(0));
+
+    let header = BlockHeaderBuilder::random()
+        .with_author(AccountAddress::random())
+        .with_author_auth_key(Some(auth_key))
+        .build();
+    let block = Block::new(header, BlockBody::new_empty());
+    assert!(block.to_metadata_checked(0).is_err());
+
+    let header = BlockHeaderBuilder::random()
+        .with_author(AccountAddress::random())
+        .with_author_auth_key(None)
+        .build();
+    let block = Block::new(header, BlockBody::new_empty());
+    assert!(block.to_metadata_checked(0).is_ok());
+}
+
+#[test]
+fn test_effective_difficulty() {
+    let header = BlockHeaderBuilder::random()
+        .with_number(10)
+        .with_difficulty(U256::from(1000))
+        .build();
+    let block = Block::new(header.clone(), BlockBody::new_empty());
+    assert_eq!(block.effective_difficulty(1, 8), U256::from(1000));
+
+    let uncles = vec![header_with_number(8), header_with_number(9)];
+    let block = Block::new(header, BlockBody::new(vec![], Some(uncles)));
+    // 1000 + 1000 * 2 * 1 / 8
+    assert_eq!(block.effective_difficulty(1, 8), U256::from(1250));
+    assert_eq!(block.effective_difficulty(0, 8), U256::from(1000));
+    assert_eq!(block.effective_difficulty(1, 0), U256::from(1000));
+}
+
+#[test]
+fn test_verify_parent_accumulator_present() {
+    let genesis = BlockHeaderBuilder::random()
+        .with_number(0)
+        .with_parent_block_accumulator_root(*ACCUMULATOR_PLACEHOLDER_HASH)
+        .build();
+    assert!(genesis.verify_parent_accumulator_present().is_ok());
+
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_parent_block_accumulator_root(*ACCUMULATOR_PLACEHOLDER_HASH)
+        .build();
+    assert!(header.verify_parent_accumulator_present().is_err());
+
+    let header = BlockHeaderBuilder::random().with_number(1).build();
+    assert!(header.verify_parent_accumulator_present().is_ok());
+}
+
+#[test]
+fn test_verify_accumulator_root() {
+    let genesis = BlockHeaderBuilder::random()
+        .with_number(0)
+        .with_accumulator_root(*ACCUMULATOR_PLACEHOLDER_HASH)
+        .build();
+    assert!(genesis.verify_accumulator_root().is_ok());
+
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_accumulator_root(*ACCUMULATOR_PLACEHOLDER_HASH)
+        .build();
+    assert!(header.verify_accumulator_root().is_err());
+
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_accumulator_root(HashValue::random())
+        .build();
+    assert!(header.verify_accumulator_root().is_ok());
+}
+
+#[test]
+fn test_into_block_checked() {
+    let parent = Block::new(header_with_number(1), BlockBody::new_empty());
+    let body = BlockBody::new(vec![SignedUserTransaction::mock()], None);
+    let template = BlockTemplate::new(
+        HashValue::random(),
+        HashValue::random(),
+        HashValue::random(),
+        0,
+        body,
+        ChainId::test(),
+        U256::from(1),
+        ConsensusStrategy::Dummy,
+        parent.to_metadata(0),
+    );
+    let block = template
+        .clone()
+        .into_block_checked(1, BlockHeaderExtra::new([0u8; 4]))
+        .unwrap();
+    assert_eq!(block.header().body_hash(), block.body.hash());
+
+    let mut corrupted = template;
+    corrupted.body_hash = HashValue::random();
+    assert!(corrupted
+        .into_block_checked(1, BlockHeaderExtra::new([0u8; 4]))
+        .is_err());
+}
+
+#[test]
+fn test_split_summary() {
+    let uncles = vec![header_with_number(8), header_with_number(9)];
+    let txns = vec![SignedUserTransaction::mock(), SignedUserTransaction::mock()];
+    let block = Block::new(
+        header_with_number(10),
+        BlockBody::new(txns.clone(), Some(uncles.clone())),
+    );
+    let (summary, split_txns) = block.clone().split_summary();
+    assert_eq!(summary.header(), block.header());
+    assert_eq!(summary.uncles(), uncles.as_slice());
+    assert_eq!(split_txns, txns);
+
+    let rebuilt = Block::new(
+        summary.block_header,
+        BlockBody::new(split_txns, Some(summary.uncles)),
+    );
+    assert_eq!(rebuilt, block);
+    assert_eq!(rebuilt.id(), block.id());
+}
+
+#[test]
+fn test_child_number() {
+    assert_eq!(header_with_number(10).child_number().unwrap(), 11);
+    assert!(header_with_number(u64::MAX).child_number().is_err());
+}
+
+#[test]
+fn test_verify_self() {
+    let block = Block::sample();
+    assert!(block.verify_self().is_ok());
+
+    let mut tampered = block.clone();
+    tampered
+        .body
+        .push_transaction(SignedUserTransaction::mock());
+    assert!(tampered.verify_body_hash().is_err());
+    assert!(tampered.verify_self().is_err());
+
+    let mut with_uncles = block;
+    with_uncles.body.uncles = Some(vec![header_with_number(0)]);
+    with_uncles.header = with_uncles
+        .header
+        .as_builder()
+        .with_body_hash(with_uncles.body.hash())
+        .build();
+    assert!(with_uncles.verify_body_hash().is_ok());
+    assert!(with_uncles.verify_genesis().is_err());
+    assert!(with_uncles.verify_self().is_err());
+}
+
+#[test]
+fn test_genesis_block_checked() {
+    let genesis_txn = txn_with_chain_id(ChainId::test());
+    let block = Block::genesis_block_checked(
+        HashValue::random(),
+        1621311100000,
+        HashValue::random(),
+        HashValue::random(),
+        U256::from(1),
+        genesis_txn.clone(),
+    )
+    .unwrap();
+    assert!(block.header().is_genesis());
+    assert_eq!(block.header().chain_id(), ChainId::test());
+    assert!(block.verify_self().is_ok());
+
+    assert!(Block::genesis_block_checked(
+        HashValue::random(),
+        1621311100000,
+        *ACCUMULATOR_PLACEHOLDER_HASH,
+        HashValue::random(),
+        U256::from(1),
+        genesis_txn.clone(),
+    )
+    .is_err());
+    assert!(Block::genesis_block_checked(
+        HashValue::random(),
+        1621311100000,
+        HashValue::random(),
+        *SPARSE_MERKLE_PLACEHOLDER_HASH,
+        U256::from(1),
+        genesis_txn,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_sum_difficulty() {
+    let block_with_difficulty = |difficulty: U256| {
+        Block::new(
+            BlockHeaderBuilder::random()
+                .with_difficulty(difficulty)
+                .build(),
+            BlockBody::new_empty(),
+        )
+    };
+    assert_eq!(sum_difficulty(&[]).unwrap(), U256::zero());
+    let blocks = vec![
+        block_with_difficulty(U256::from(1)),
+        block_with_difficulty(U256::from(2)),
+        block_with_difficulty(U256::from(3)),
+    ];
+    assert_eq!(sum_difficulty(&blocks).unwrap(), U256::from(6));
+
+    let blocks = vec![
+        block_with_difficulty(U256::max_value() - 1),
+        block_with_difficulty(U256::from(1)),
+    ];
+    assert_eq!(sum_difficulty(&blocks).unwrap(), U256::max_value());
+    let blocks = vec![
+        block_with_difficulty(U256::max_value()),
+        block_with_difficulty(U256::from(1)),
+    ];
+    assert!(sum_difficulty(&blocks).is_err());
+}
+
+#[test]
+fn test_verify_uncle_recency() {
+    let header = header_with_number(10);
+    let block = Block::new(header.clone(), BlockBody::new_empty());
+    assert!(block.verify_uncle_recency(0).is_ok());
+
+    let uncles = vec![header_with_number(9), header_with_number(7)];
+    let block = Block::new(header, BlockBody::new(vec![], Some(uncles)));
+    assert!(block.verify_uncle_recency(3).is_ok());
+    assert!(block.verify_uncle_recency(2).is_err());
+}
+
+#[test]
+fn test_verify_uncle_chain_ids() {
+    let header = header_with_number(10);
+    let uncles = vec![header_with_number(9), header_with_number(8)];
+    let block = Block::new(header.clone(), BlockBody::new(vec![], Some(uncles.clone())));
+    assert!(block.verify_uncle_chain_ids().is_ok());
+
+    let mut uncles = uncles;
+    uncles.push(
+        BlockHeaderBuilder::random()
+            .with_number(9)
+            .with_chain_id(ChainId::new(1))
+            .build(),
+    );
+    let block = Block::new(header, BlockBody::new(vec![], Some(uncles)));
+    assert!(block.verify_uncle_chain_ids().is_err());
+}
+
+#[test]
+fn test_author_auth_key_bytes() {
+    let auth_key = AuthenticationKey::random();
+    let header = BlockHeaderBuilder::random()
+        .with_author_auth_key(Some(auth_key))
+        .build();
+    assert_eq!(header.author_auth_key(), Some(auth_key));
+    assert_eq!(header.author_auth_key_bytes(), auth_key.to_vec());
+
+    let header = BlockHeaderBuilder::random()
+        .with_author_auth_key(None)
+        .build();
+    assert_eq!(header.author_auth_key(), None);
+    assert!(header.author_auth_key_bytes().is_empty());
+}
+
+#[test]
+fn test_to_template() {
+    let header = BlockHeaderBuilder::random()
+        .with_author_auth_key(None)
+        .build();
+    let block = Block::new(header, vec![SignedUserTransaction::mock()]);
+    let template = block.to_template(ConsensusStrategy::Dummy);
+    assert_eq!(template.strategy, ConsensusStrategy::Dummy);
+    assert_eq!(template.body, block.body);
+    let raw_header: RawBlockHeader = block.header.clone().into();
+    assert_eq!(template.as_raw_block_header(), raw_header);
+
+    let remined = template.into_block(block.header.nonce(), *block.header.extra());
+    assert_eq!(remined.header, block.header);
+}
+
+#[test]
+fn test_extra_verify_reserved_zero() {
+    let reserved_mask = 0xffff_0000u32;
+    let extra = BlockHeaderExtra::new([0, 0, 0x12, 0x34]);
+    assert!(extra.verify_reserved_zero(reserved_mask).is_ok());
+    assert!(BlockHeaderExtra::default()
+        .verify_reserved_zero(u32::MAX)
+        .is_ok());
+
+    let extra = BlockHeaderExtra::new([0, 0x01, 0x12, 0x34]);
+    assert!(extra.verify_reserved_zero(reserved_mask).is_err());
+    assert!(extra.verify_reserved_zero(0x0000_ffff).is_err());
+    assert!(extra.verify_reserved_zero(0).is_ok());
+}
+
+#[test]
+fn test_header_builder_try_build() {
+    assert!(BlockHeaderBuilder::new().try_build().is_err());
+    assert!(BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_parent_hash(HashValue::zero())
+        .try_build()
+        .is_err());
+
+    let header = BlockHeaderBuilder::new()
+        .with_number(0)
+        .with_timestamp(1610110515000)
+        .with_body_hash(HashValue::random())
+        .try_build()
+        .unwrap();
+    assert_eq!(header.number(), 0);
+
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .try_build()
+        .unwrap();
+    assert_eq!(header.number(), 1);
+}
+
+#[test]
+fn test_parse_pow_header_blob() {
+    let header = BlockHeader::random();
+    let blob = header.as_pow_header_blob();
+    let (raw_header_hash, difficulty) = parse_pow_header_blob(&blob).unwrap();
+    let raw_header: RawBlockHeader = header.clone().into();
+    assert_eq!(raw_header_hash, raw_header.crypto_hash());
+    assert_eq!(difficulty, header.difficulty());
+
+    let template = BlockTemplate::from_block(Block::new(header, vec![]), ConsensusStrategy::Dummy);
+    let (raw_header_hash, difficulty) =
+        parse_pow_header_blob(&template.as_pow_header_blob()).unwrap();
+    assert_eq!(
+        raw_header_hash,
+        template.as_raw_block_header().crypto_hash()
+    );
+    assert_eq!(difficulty, template.difficulty);
+
+    assert!(parse_pow_header_blob(&blob[..75]).is_err());
+    let mut longer = blob.clone();
+    longer.push(0);
+    assert!(parse_pow_header_blob(&longer).is_err());
+    let mut solved = blob;
+    solved[40] = 1;
+    assert!(parse_pow_header_blob(&solved).is_err());
+}
+
+#[test]
+fn test_header_verify_child() {
+    let parent = BlockHeaderBuilder::random()
+        .with_number(10)
+        .with_timestamp(1000)
+        .build();
+    let child = BlockHeaderBuilder::random()
+        .with_parent_hash(parent.id())
+        .with_number(11)
+        .with_timestamp(1000)
+        .build();
+    assert_eq!(parent.verify_child(&child), Ok(()));
+
+    let mismatch = child
+        .as_builder()
+        .with_parent_hash(HashValue::random())
+        .build();
+    assert_eq!(
+        parent.verify_child(&mismatch),
+        Err(BlockHeaderVerifyError::ParentMismatch {
+            expect: parent.id(),
+            real: mismatch.parent_hash(),
+        })
+    );
+
+    let skipped = child.as_builder().with_number(12).build();
+    assert_eq!(
+        parent.verify_child(&skipped),
+        Err(BlockHeaderVerifyError::NumberNotSequential {
+            parent: 10,
+            real: 12
+        })
+    );
+
+    let regressed = child.as_builder().with_timestamp(999).build();
+    assert_eq!(
+        parent.verify_child(&regressed),
+        Err(BlockHeaderVerifyError::TimestampRegression {
+            parent: 1000,
+            real: 999
+        })
+    );
+}
+
+#[test]
+fn test_transaction_id_commitment() {
+    let header = BlockHeader::random();
+    let txn1 = txn_with_chain_id(ChainId::test());
+    let txn2 = txn_with_chain_id(ChainId::test());
+    let block = Block::new(header.clone(), vec![txn1.clone()]);
+    let commitment = block.transaction_id_commitment();
+    assert_eq!(
+        commitment,
+        Block::new(header.clone(), vec![txn1.clone()]).transaction_id_commitment()
+    );
+
+    let mut body = block.body.clone();
+    body.push_transaction(txn2.clone());
+    let extended = Block::new(header.clone(), body);
+    assert_ne!(extended.transaction_id_commitment(), commitment);
+    assert_ne!(
+        extended.transaction_id_commitment(),
+        Block::new(header, vec![txn2, txn1]).transaction_id_commitment()
+    );
+}
+
+#[test]
+fn test_header_verify_timestamp() {
+    let now = 1610110515000u64;
+    let at_boundary = BlockHeaderBuilder::random()
+        .with_timestamp(now + ALLOWED_FUTURE_BLOCKTIME)
+        .build();
+    assert!(at_boundary.verify_timestamp(now).is_ok());
+
+    let over = BlockHeaderBuilder::random()
+        .with_timestamp(now + ALLOWED_FUTURE_BLOCKTIME + 1)
+        .build();
+    assert!(over.verify_timestamp(now).is_err());
+
+    let past = BlockHeaderBuilder::random()
+        .with_timestamp(now - 3_600_000)
+        .build();
+    assert!(past.verify_timestamp(now).is_ok());
+}
+
+#[test]
+fn test_raw_block_header_from_header() {
+    fn to_raw<T: Into<RawBlockHeader>>(header: T) -> RawBlockHeader {
+        header.into()
+    }
+    let header = BlockHeader::random();
+    let raw_header = RawBlockHeader::from(header.clone());
+    assert_eq!(to_raw(header.clone()), raw_header);
+    assert_eq!(raw_header.parent_hash, header.parent_hash());
+    assert_eq!(raw_header.number, header.number());
+    assert_eq!(raw_header.body_hash, header.body_hash());
+}
+
+#[test]
+fn test_header_version_compat() {
+    #[derive(serde::Serialize)]
+    #[serde(rename = "BlockHeader")]
+    struct LegacyBlockHeader {
+        parent_hash: HashValue,
+        timestamp: u64,
+        number: BlockNumber,
+        author: AccountAddress,
+        author_auth_key: Option<AuthenticationKey>,
+        txn_accumulator_root: HashValue,
+        block_accumulator_root: HashValue,
+        state_root: HashValue,
+        gas_used: u64,
+        difficulty: U256,
+        body_hash: HashValue,
+        chain_id: ChainId,
+        nonce: u32,
+        extra: BlockHeaderExtra,
+    }
+
+    let header = BlockHeaderBuilder::random()
+        .with_author_auth_key(Some(AuthenticationKey::random()))
+        .build();
+    assert_eq!(header.version(), 0);
+    let legacy = LegacyBlockHeader {
+        parent_hash: header.parent_hash(),
+        timestamp: header.timestamp(),
+        number: header.number(),
+        author: header.author(),
+        author_auth_key: header.author_auth_key(),
+        txn_accumulator_root: header.txn_accumulator_root(),
+        block_accumulator_root: header.block_accumulator_root(),
+        state_root: header.state_root(),
+        gas_used: header.gas_used(),
+        difficulty: header.difficulty(),
+        body_hash: header.body_hash(),
+        chain_id: header.chain_id(),
+        nonce: header.nonce(),
+        extra: *header.extra(),
+    };
+    let legacy_bytes = bcs_ext::to_bytes(&legacy).unwrap();
+    assert_eq!(bcs_ext::to_bytes(&header).unwrap(), legacy_bytes);
+    let decoded: BlockHeader = bcs_ext::from_bytes(&legacy_bytes).unwrap();
+    assert_eq!(decoded.id(), header.id());
+    assert_eq!(decoded.version(), 0);
+
+    let json = serde_json::to_string(&header).unwrap();
+    assert!(!json.contains("\"version\""));
+    let decoded: BlockHeader = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.id(), header.id());
+    // the JSON written before, with plain numbers, is still decoded.
+    let legacy_json = serde_json::to_string(&legacy).unwrap();
+    let decoded: BlockHeader = serde_json::from_str(&legacy_json).unwrap();
+    assert_eq!(decoded.id(), header.id());
+
+    let versioned = header.as_builder().with_version(1).build();
+    assert_ne!(versioned.id(), header.id());
+    assert_ne!(versioned, header);
+    assert_eq!(versioned.as_pow_header_blob(), header.as_pow_header_blob());
+    let json = serde_json::to_string(&versioned).unwrap();
+    let decoded: BlockHeader = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.version(), 1);
+    assert_eq!(decoded.id(), versioned.id());
+
+    // the binary form appends the version, with or without an auth key, also when nested.
+    for versioned in [
+        versioned.clone(),
+        versioned.as_builder().with_author_auth_key(None).build(),
+    ] {
+        let bytes = bcs_ext::to_bytes(&versioned).unwrap();
+        assert_ne!(bytes, legacy_bytes);
+        let decoded: BlockHeader = bcs_ext::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.version(), 1);
+        assert_eq!(decoded.author_auth_key(), versioned.author_auth_key());
+        assert_eq!(decoded.id(), versioned.id());
+        assert_eq!(decoded, versioned);
+
+        let headers = vec![versioned.clone(), header.clone()];
+        let bytes = bcs_ext::to_bytes(&headers).unwrap();
+        let decoded: Vec<BlockHeader> = bcs_ext::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, headers);
+        assert_eq!(decoded[0].version(), 1);
+        assert_eq!(decoded[1].version(), 0);
+    }
+}
+
+#[test]
+fn test_verify_body_hash_mutated_transactions() {
+    let body = BlockBody::new(vec![txn_with_chain_id(ChainId::test())], None);
+    let header = BlockHeaderBuilder::random()
+        .with_body_hash(body.hash())
+        .build();
+    let block = Block::new(header, body);
+    assert!(block.verify_body_hash().is_ok());
+
+    let mut tampered = block.clone();
+    tampered.body.transactions[0] = txn_with_chain_id(ChainId::test());
+    let err = tampered.verify_body_hash().unwrap_err().to_string();
+    assert!(err.contains(&tampered.body.hash().to_string()));
+    assert!(err.contains(&block.header.body_hash().to_string()));
+}
+
+#[test]
+fn test_compressed_size() {
+    let txn = txn_with_chain_id(ChainId::test());
+    let block = Block::new(BlockHeader::random(), vec![txn; 50]);
+    let compressed_size = block.compressed_size().unwrap();
+    assert!(compressed_size > 0);
+    assert!(compressed_size < block.encoded_size().unwrap());
+}
+
+#[test]
+fn test_block_counts() {
+    let block = Block::new(BlockHeader::random(), BlockBody::new_empty());
+    assert_eq!(block.txn_count(), 0);
+    assert_eq!(block.uncle_count(), 0);
+    assert_eq!(block.total_txn_count_with_uncles(), 0);
+    assert_eq!(block.txn_and_uncle_count(), 0);
+
+    let txns = vec![SignedUserTransaction::mock(), SignedUserTransaction::mock()];
+    let uncles = vec![header_with_number(1)];
+    let block = Block::new(BlockHeader::random(), BlockBody::new(txns, Some(uncles)));
+    assert_eq!(block.txn_count(), 2);
+    assert_eq!(block.uncle_count(), 1);
+    assert_eq!(block.total_txn_count_with_uncles(), 2);
+    assert_eq!(block.txn_and_uncle_count(), 3);
+
+    let block = Block::new(BlockHeader::random(), BlockBody::new(vec![], Some(vec![])));
+    assert_eq!(block.uncle_count(), 0);
+}
+
+#[test]
+fn test_encode_to_writer() {
+    let txn = txn_with_chain_id(ChainId::test());
+    let block = Block::new(BlockHeader::random(), vec![txn; 5000]);
+    let encoded_size = block.encoded_size().unwrap();
+
+    // The writer is never grown, so the block is encoded without allocation.
+    let mut buf = Vec::with_capacity(encoded_size);
+    block.encode_to_writer(&mut buf).unwrap();
+    assert_eq!(buf.len(), encoded_size);
+    assert_eq!(buf.capacity(), encoded_size);
+    assert_eq!(buf, bcs_ext::to_bytes(&block).unwrap());
+
+    let decoded = Block::decode_from_reader(&mut buf.as_slice()).unwrap();
+    assert_eq!(decoded, block);
+}
+
+#[test]
+fn test_verify_timestamp_nonzero() {
+    let header = BlockHeaderBuilder::random()
+        .with_number(1)
+        .with_timestamp(1610110515000)
+        .build();
+    assert!(header.verify_timestamp_nonzero().is_ok());
+
+    let header = header.as_builder().with_timestamp(0).build();
+    assert!(header.verify_timestamp_nonzero().is_err());
+
+    let genesis = header.as_builder().with_number(0).build();
+    assert!(genesis.verify_timestamp_nonzero().is_ok());
+}
+
+#[test]
+fn test_body_retain_transactions() {
+    let txn1 = txn_with_chain_id(ChainId::test());
+    let txn2 = txn_with_chain_id(ChainId::test());
+    let mut body = BlockBody::new(vec![txn1.clone()], None);
+    body.push_transaction(txn2.clone());
+    let hash = body.hash();
+
+    body.retain_transactions(|_| true);
+    assert_eq!(body.hash(), hash);
+
+    body.retain_transactions(|txn| txn.id() != txn2.id());
+    assert_eq!(body.transactions, vec![txn1]);
+    assert_ne!(body.hash(), hash);
+}
+
+#[test]
+fn test_body_new_checked() {
+    let txn1 = txn_with_chain_id(ChainId::test());
+    let txn2 = txn_with_chain_id(ChainId::test());
+    let body = BlockBody::new_checked(vec![txn1.clone(), txn2.clone()], None).unwrap();
+    assert_eq!(body.transactions.len(), 2);
+
+    let err = BlockBody::new_checked(vec![txn1.clone(), txn2, txn1.clone()], None).unwrap_err();
+    assert!(err.to_string().contains(&txn1.id().to_string()));
+}
+
+#[test]
+fn test_verify_uncles_structural() {
+    let header = header_with_number(10);
+    let uncles = vec![header_with_number(9), header_with_number(8)];
+    let block = Block::new(header.clone(), BlockBody::new(vec![], Some(uncles.clone())));
+    assert!(block.verify_uncles_structural().is_ok());
+
+    let bad_uncle = uncles[0].as_builder().with_difficulty(U256::zero()).build();
+    assert!(bad_uncle.verify_difficulty().is_err());
+    let mut uncles = uncles;
+    uncles.push(bad_uncle.clone());
+    let block = Block::new(header, BlockBody::new(vec![], Some(uncles)));
+    let err = block.verify_uncles_structural().unwrap_err();
+    assert!(err.to_string().contains(&bad_uncle.id().to_string()));
+}
+
+#[test]
+fn test_iter_transactions() {
+    let txns = vec![SignedUserTransaction::mock(), SignedUserTransaction::mock()];
+    let uncles = vec![header_with_number(1)];
+    let block = Block::new(
+        BlockHeader::random(),
+        BlockBody::new(txns.clone(), Some(uncles)),
+    );
+    let iter = block.iter_transactions();
+    assert_eq!(iter.len(), 2);
+    assert_eq!(iter.cloned().collect::<Vec<_>>(), txns);
+}
+
+#[test]
+fn test_block_info_verify_against_header() {
+    let header = BlockHeaderBuilder::random()
+        .with_difficulty(U256::from(10))
+        .build();
+    let block_info = BlockInfo::new(
+        header.id(),
+        U256::from(100),
+        AccumulatorInfo::sample(),
+        AccumulatorInfo::sample(),
+    );
+    assert!(block_info.verify_against_header(&header).is_ok());
+
+    let other = BlockHeaderBuilder::random()
+        .with_difficulty(U256::from(10))
+        .build();
+    assert!(block_info.verify_against_header(&other).is_err());
+
+    let block_info = BlockInfo::new(
+        header.id(),
+        U256::from(1),
+        AccumulatorInfo::sample(),
+        AccumulatorInfo::sample(),
+    );
+    assert!(block_info.verify_against_header(&header).is_err());
+}
+
+#[test]
+fn test_reward_inputs() {
+    let header = BlockHeaderBuilder::random()
+        .with_number(10)
+        .with_gas_used(1000)
+        .build();
+    let uncles = vec![header_with_number(9), header_with_number(8)];
+    let block = Block::new(header.clone(), BlockBody::new(vec![], Some(uncles.clone())));
+    assert_eq!(
+        block.reward_inputs(),
+        RewardInputs {
+            author: header.author(),
+            uncle_authors: vec![uncles[0].author(), uncles[1].author()],
+            gas_used: 1000,
+            block_number: 10,
+        }
+    );
+}
+
+#[test]
+fn test_block_info_get_ancestor_proof() {
+    let store = Arc::new(MockAccumulatorStore::new());
+    let accumulator = MerkleAccumulator::new_empty(store.clone());
+    let block_ids: Vec<HashValue> = (0..5).map(|_| HashValue::random()).collect();
+    accumulator.append(&block_ids).unwrap();
+    accumulator.flush().unwrap();
+    let block_accumulator_info = accumulator.get_info();
+    let block_info = BlockInfo::new(
+        block_ids[4],
+        U256::from(100),
+        AccumulatorInfo::sample(),
+        block_accumulator_info.clone(),
+    );
+
+    let proof = block_info.get_ancestor_proof(store.clone(), 2).unwrap();
+    assert!(proof
+        .verify(block_accumulator_info.accumulator_root, block_ids[2], 2)
+        .is_ok());
+    assert!(proof
+        .verify(block_accumulator_info.accumulator_root, block_ids[3], 2)
+        .is_err());
+    assert!(block_info.get_ancestor_proof(store, 5).is_err());
+}
+
+#[test]
+fn test_executed_block_gas_used_per_txn() {
+    let block = Block::new(BlockHeader::random(), BlockBody::new_empty());
+    let executed_block = ExecutedBlock::new(block, BlockInfo::sample());
+    assert_eq!(executed_block.gas_used_per_txn(), None);
+
+    let executed_block = executed_block.with_gas_used_per_txn(vec![0, 100, 200]);
+    assert_eq!(
+        executed_block.gas_used_per_txn(),
+        Some(&[0u64, 100, 200][..])
+    );
+
+    let decoded: ExecutedBlock =
+        bcs_ext::from_bytes(&bcs_ext::to_bytes(&executed_block).unwrap()).unwrap();
+    assert_eq!(decoded.gas_used_per_txn(), None);
+}
+
+#[test]
+fn test_executed_block_eq() {
+    let block = Block::new(BlockHeader::random(), BlockBody::new_empty());
+    let executed_block = ExecutedBlock::new(block, BlockInfo::sample());
+    assert_eq!(executed_block, executed_block.clone());
+    assert_eq!(
+        executed_block,
+        executed_block.clone().with_gas_used_per_txn(vec![0])
+    );
+
+    let other = ExecutedBlock::new(
+        Block::new(BlockHeader::random(), BlockBody::new_empty()),
+        BlockInfo::sample(),
+    );
+    assert_ne!(executed_block, other);
+
+    let set: std::collections::HashSet<ExecutedBlock> =
+        vec![executed_block.clone(), executed_block, other]
+            .into_iter()
+            .collect();
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn test_block_template_verify() {
+    let parent = Block::new(header_with_number(1), BlockBody::new_empty());
+    let body = BlockBody::new(vec![SignedUserTransaction::mock()], None);
+    let template = BlockTemplate::new(
+        HashValue::random(),
+        HashValue::random(),
+        HashValue::random(),
+        0,
+        body,
+        ChainId::test(),
+        U256::from(1),
+        ConsensusStrategy::Dummy,
+        parent.to_metadata(0),
+    );
+    assert!(template.verify().is_ok());
+
+    let mut mutated = template.clone();
+    mutated
+        .body
+        .push_transaction(txn_with_chain_id(ChainId::test()));
+    assert!(mutated.verify().is_err());
+    assert!(mutated
+        .into_block_checked(1, BlockHeaderExtra::new([0u8; 4]))
+        .is_err());
+
+    let mut zero_difficulty = template;
+    zero_difficulty.difficulty = U256::zero();
+    assert!(zero_difficulty.verify().is_err());
+}
+
+#[test]
+fn test_block_template_verify_uncles() {
+    let uncles = vec![header_with_number(9), header_with_number(8)];
+    let block = Block::new(header_with_number(10), BlockBody::new(vec![], Some(uncles)));
+    let template = BlockTemplate::from_block(block, ConsensusStrategy::Dummy);
+    assert!(template.verify_uncles(2).is_ok());
+    assert!(template.verify_uncles(1).is_err());
+
+    let block = Block::new(
+        header_with_number(10),
+        BlockBody::new(vec![], Some(vec![header_with_number(10)])),
+    );
+    let template = BlockTemplate::from_block(block, ConsensusStrategy::Dummy);
+    assert!(template.verify_uncles(2).is_err());
+}
+
+#[test]
+fn test_block_weight() {
+    let header = BlockHeaderBuilder::random()
+        .with_number(10)
+        .with_difficulty(U256::from(100))
+        .build();
+    let block = Block::new(header.clone(), BlockBody::new_empty());
+    assert_eq!(block.weight(), U256::from(100));
+
+    let uncles = vec![
+        header_with_number(9)
+            .as_builder()
+            .with_difficulty(U256::from(20))
+            .build(),
+        header_with_number(8)
+            .as_builder()
+            .with_difficulty(U256::from(30))
+            .build(),
+    ];
+    let block = Block::new(header, BlockBody::new(vec![], Some(uncles)));
+    // uncles add no weight, like the total difficulty.
+    assert_eq!(block.weight(), U256::from(100));
+    assert_eq!(block.weight(), block.effective_difficulty(0, 1));
+
+    let parent_total_difficulty = U256::from(900);
+    let mut block_info = BlockInfo::sample();
+    block_info.total_difficulty = parent_total_difficulty + block.weight();
+    let executed_block = ExecutedBlock::new(block, block_info);
+    assert_eq!(executed_block.cumulative_weight(), U256::from(1000));
+}
+
+#[test]
+fn test_header_json_u64_as_string() {
+    let header = BlockHeaderBuilder::random()
+        .with_gas_used(u64::MAX)
+        .with_difficulty(U256::max_value())
+        .build();
+    let json = serde_json::to_value(&header).unwrap();
+    assert_eq!(json["gas_used"], serde_json::json!(u64::MAX.to_string()));
+    assert_eq!(
+        json["difficulty"],
+        serde_json::to_value(U256::max_value()).unwrap()
+    );
+    let decoded: BlockHeader = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(decoded.gas_used(), u64::MAX);
+    assert_eq!(decoded.id(), header.id());
+
+    let mut numbers = json;
+    numbers["gas_used"] = serde_json::json!(u64::MAX);
+    numbers["timestamp"] = serde_json::json!(header.timestamp());
+    numbers["number"] = serde_json::json!(header.number());
+    let decoded: BlockHeader = serde_json::from_value(numbers).unwrap();
+    assert_eq!(decoded.id(), header.id());
+
+    let bytes = bcs_ext::to_bytes(&header).unwrap();
+    let decoded: BlockHeader = bcs_ext::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded, header);
+}
+
+#[test]
+fn test_constant_time_eq() {
+    let hash = HashValue::random();
+    assert!(constant_time_eq(&hash, &hash));
+    assert!(!constant_time_eq(&hash, &HashValue::random()));
+    let mut bytes = hash.to_vec();
+    bytes[HashValue::LENGTH - 1] ^= 1;
+    assert!(!constant_time_eq(
+        &hash,
+        &HashValue::from_slice(&bytes).unwrap()
+    ));
+}
+
+#[test]
+fn test_block_header_extra_display_from_str() {
+    let extra = BlockHeaderExtra::new([0x01, 0x02, 0x03, 0xab]);
+    let display = extra.to_string();
+    assert_eq!(display, "0x010203ab");
+    assert_eq!(display.parse::<BlockHeaderExtra>().unwrap(), extra);
+    assert_eq!(
+        serde_json::to_string(&extra).unwrap(),
+        format!("\"{}\"", display)
+    );
+    assert_eq!("010203ab".parse::<BlockHeaderExtra>().unwrap(), extra);
+    assert!("0x0102".parse::<BlockHeaderExtra>().is_err());
+    assert!("0x010203zz".parse::<BlockHeaderExtra>().is_err());
+}
+
+#[test]
+fn test_header_verify_genesis() {
+    let genesis = BlockHeader::sample();
+    assert!(genesis.verify_genesis().is_ok());
+
+    let corrupted = vec![
+        genesis.as_builder().with_number(1).build(),
+        genesis
+            .as_builder()
+            .with_author(AccountAddress::random())
+            .build(),
+        genesis
+            .as_builder()
+            .with_author_auth_key(Some(AuthenticationKey::random()))
+            .build(),
+        genesis
+            .as_builder()
+            .with_parent_block_accumulator_root(HashValue::random())
+            .build(),
+        genesis.as_builder().with_gas_used(1).build(),
+    ];
+    for header in corrupted {
+        assert!(header.verify_genesis().is_err());
+    }
+}
+
+#[test]
+fn test_block_verify_chain_id() {
+    let chain_id = ChainId::test();
+    let header = BlockHeaderBuilder::random().with_chain_id(chain_id).build();
+    let txns = vec![txn_with_chain_id(chain_id), txn_with_chain_id(chain_id)];
+    let block = Block::new(header.clone(), txns.clone());
+    assert!(block.verify_chain_id().is_ok());
+
+    let mut mixed_txns = txns.clone();
+    mixed_txns.push(txn_with_chain_id(ChainId::new(1)));
+    let block = Block::new(header.clone(), mixed_txns);
+    let err = block.verify_chain_id().unwrap_err();
+    assert!(err.to_string().contains("index 2"));
+
+    let foreign_uncle = header_with_number(1)
+        .as_builder()
+        .with_chain_id(ChainId::new(1))
+        .build();
+    let block = Block::new(header, BlockBody::new(txns, Some(vec![foreign_uncle])));
+    assert!(block.verify_chain_id().is_err());
+}
+
+#[test]
+fn test_pow_target() {
+    let header = BlockHeaderBuilder::random()
+        .with_difficulty(U256::from(1))
+        .build();
+    assert_eq!(header.pow_target(), U256::max_value());
+    assert_eq!(
+        RawBlockHeader::from(header.clone()).pow_target(),
+        U256::max_value()
+    );
+    assert!(header.verify_pow(U256::max_value()));
+
+    let difficulty = U256::from(1u64 << 40);
+    let header = header.as_builder().with_difficulty(difficulty).build();
+    let target = U256::max_value() / difficulty;
+    assert_eq!(header.pow_target(), target);
+    assert_eq!(RawBlockHeader::from(header.clone()).pow_target(), target);
+    assert!(header.verify_pow(target));
+    assert!(header.verify_pow(U256::zero()));
+    assert!(!header.verify_pow(target + U256::from(1)));
+
+    assert_eq!(pow_target(U256::zero()), U256::max_value());
+}
+
+#[test]
+fn test_uncle_summary_from_summaries() {
+    let summary = |number: u64, timestamp: u64, uncles: Vec<(u64, u64)>| BlockSummary {
+        block_header: header_with_number(number)
+            .as_builder()
+            .with_timestamp(timestamp)
+            .build(),
+        uncles: uncles
+            .into_iter()
+            .map(|(number, timestamp)| {
+                header_with_number(number)
+                    .as_builder()
+                    .with_timestamp(timestamp)
+                    .build()
+            })
+            .collect(),
+    };
+    let summaries = vec![
+        summary(10, 10_000, vec![(9, 9_000), (8, 8_000)]),
+        summary(20, 20_000, vec![(17, 18_500)]),
+        summary(21, 21_000, vec![]),
+    ];
+    let uncle_summary = UncleSummary::from_summaries(&summaries);
+    assert_eq!(uncle_summary.uncles, 3);
+    assert_eq!(uncle_summary.sum, 6);
+    assert_eq!(uncle_summary.avg, 2);
+    assert_eq!(uncle_summary.time_sum, 4_500);
+    assert_eq!(uncle_summary.time_avg, 1_500);
+
+    let empty = UncleSummary::from_summaries(&[]);
+    assert_eq!(empty.uncles, 0);
+    assert_eq!(empty.avg, 0);
+}
+
+#[test]
+fn test_summary_crypto_hash() {
+    let block = Block::new(
+        header_with_number(10),
+        BlockBody::new(vec![], Some(vec![header_with_number(9)])),
+    );
+    let summary = BlockSummary::from(block.clone());
+    let same_summary = BlockSummary::from(block);
+    assert_eq!(summary, same_summary);
+    assert_eq!(summary.crypto_hash(), same_summary.crypto_hash());
+    let other_summary =
+        BlockSummary::from(Block::new(header_with_number(10), BlockBody::new_empty()));
+    assert_ne!(summary.crypto_hash(), other_summary.crypto_hash());
+
+    let uncle_summary = UncleSummary::from_summaries(&[summary]);
+    assert_eq!(
+        uncle_summary.crypto_hash(),
+        UncleSummary::new(1, 1, uncle_summary.time_sum).crypto_hash()
+    );
+    let epoch_summary = EpochUncleSummary::new(1, uncle_summary.clone(), uncle_summary.clone());
+    assert_eq!(
+        epoch_summary.crypto_hash(),
+        EpochUncleSummary::new(1, uncle_summary.clone(), uncle_summary).crypto_hash()
+    );
+}
+
+#[test]
+fn test_block_encoded_size() {
+    let empty = Block::new(BlockHeader::random(), BlockBody::new_empty());
+    assert_eq!(
+        empty.encoded_size().unwrap(),
+        bcs_ext::to_bytes(&empty).unwrap().len()
+    );
+    let body = BlockBody::new(
+        vec![txn_with_chain_id(ChainId::test()); 10],
+        Some(vec![header_with_number(1)]),
+    );
+    let block = Block::new(BlockHeader::random(), body);
+    assert_eq!(
+        block.encoded_size().unwrap(),
+        bcs_ext::to_bytes(&block).unwrap().len()
+    );
+    assert!(block.encoded_size().unwrap() > empty.encoded_size().unwrap());
+    assert_eq!(
+        block.serialized_size().unwrap(),
+        bcs_ext::to_bytes(&block).unwrap().len()
+    );
+}
+
+#[test]
+fn test_block_id_and_number_order() {
+    let genesis = BlockIdAndNumber::genesis(HashValue::random());
+    assert_eq!(genesis.number(), 0);
+
+    let (low_id, high_id) = {
+        let (a, b) = (HashValue::random(), HashValue::random());
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    };
+    let low = BlockIdAndNumber::new(high_id, 1);
+    let high = BlockIdAndNumber::new(low_id, 2);
+    // the derived order compares id first.
+    assert_eq!(low.cmp(&high), Ordering::Greater);
+    assert_eq!(low.cmp_by_number(&high), Ordering::Less);
+    assert!(genesis.is_ancestor_candidate(&low));
+    assert!(low.is_ancestor_candidate(&high));
+    assert!(!high.is_ancestor_candidate(&low));
+
+    let tip_a = BlockIdAndNumber::new(low_id, 3);
+    let tip_b = BlockIdAndNumber::new(high_id, 3);
+    assert_eq!(tip_a.cmp_by_number(&tip_b), Ordering::Less);
+    assert_eq!(tip_b.cmp_by_number(&tip_a), Ordering::Greater);
+    assert_eq!(tip_a.cmp_by_number(&tip_a), Ordering::Equal);
+    assert!(!tip_a.is_ancestor_candidate(&tip_b));
+    assert!(!tip_b.is_ancestor_candidate(&tip_a));
+}
+
+#[test]
+fn test_pow_blob_with_nonce() {
+    let header = BlockHeader::random();
+    assert_eq!(header.pow_blob_with_nonce(0), header.as_pow_header_blob());
+
+    let header = header
+        .as_builder()
+        .with_extra(BlockHeaderExtra::new([5u8, 6, 7, 8]))
+        .build();
+    let blob_a = header.pow_blob_with_nonce(1);
+    let blob_b = header.pow_blob_with_nonce(0x0102_0304);
+    assert_eq!(blob_a.len(), POW_HEADER_BLOB_LENGTH);
+    assert_ne!(blob_a, blob_b);
+    let nonce_range = POW_HEADER_NONCE_OFFSET..POW_HEADER_NONCE_OFFSET + 4;
+    for (i, (a, b)) in blob_a.iter().zip(blob_b.iter()).enumerate() {
+        if !nonce_range.contains(&i) {
+            assert_eq!(a, b, "blobs differ outside the nonce at byte {}", i);
+        }
+    }
+    assert_eq!(&blob_b[nonce_range], &[4u8, 3, 2, 1]);
+    assert_eq!(
+        &blob_a[POW_HEADER_EXTRA_OFFSET..POW_HEADER_NONCE_OFFSET],
+        &[5u8, 6, 7, 8]
+    );
+}
+
+#[test]
+fn test_block_body_builder_hash_cached() {
+    let uncles = Some(vec![header_with_number(1)]);
+    let mut builder = BlockBodyBuilder::new(uncles.clone());
+    assert_eq!(
+        builder.hash_cached(),
+        BlockBody::new(vec![], uncles.clone()).hash()
+    );
+
+    let txns = vec![
+        txn_with_chain_id(ChainId::test()),
+        txn_with_chain_id(ChainId::test()),
+    ];
+    builder.push_transaction(txns[0].clone());
+    let hash = builder.hash_cached();
+    assert_eq!(
+        hash,
+        BlockBody::new(txns[..1].to_vec(), uncles.clone()).hash()
+    );
+    assert_eq!(builder.hash_cached(), hash);
+
+    builder.extend_transactions(txns[1..].to_vec());
+    assert_ne!(builder.hash_cached(), hash);
+    assert_eq!(builder.transactions(), txns.as_slice());
+    let hash = builder.hash_cached();
+    let body = builder.build();
+    assert_eq!(body, BlockBody::new(txns, uncles));
+    assert_eq!(body.hash(), hash);
+}
@@ -23,6 +23,8 @@ pub mod account_state;
 
 #[allow(clippy::too_many_arguments)]
 pub mod block;
+#[cfg(test)]
+mod block_test;
 pub mod cmpact_block;
 
 pub mod block_metadata {